@@ -0,0 +1,171 @@
+use alloc::format;
+use alloc::vec::Vec;
+
+use crate::configuration::typed_num;
+use crate::hardware_interface::State;
+use crate::hardware_interface::Switch;
+use crate::hardware_interface::NUM_PUMPS;
+use crate::machine::Machine;
+use crate::melody::CONFIRM_SELECTION;
+use crate::melody::ERROR;
+use crate::programs::Program;
+use crate::Duration;
+
+/// Long enough that the measurement is not dominated by the time the
+/// pump needs to get going.
+const CALIBRATION_POUR_DURATION_MS: Duration = 5000;
+const MAX_CALIBRATION_VOLUME_ML: usize = 2000;
+
+enum CalibrationState {
+    WaitForGlass,
+    WaitForVolume,
+}
+
+/// Pours for a fixed time and derives the flow rate from the volume
+/// the user measured, so that shots can be configured in ml. The
+/// number keys select the pump before pouring, the first one is
+/// calibrated by default.
+pub struct CalibrationProgram {
+    state: CalibrationState,
+    pump: usize,
+    typed_digits: Vec<usize>,
+}
+
+impl CalibrationProgram {
+    pub fn new(machine: &mut Machine) -> Self {
+        machine.report("Put a measuring cup under Dwight and press right".into());
+        Self {
+            state: CalibrationState::WaitForGlass,
+            pump: 0,
+            typed_digits: Vec::new(),
+        }
+    }
+
+    fn wait_for_glass(&mut self, machine: &mut Machine, state: &State) {
+        if let Some(num) = state.lowest_pressed_number_key() {
+            if (1..=NUM_PUMPS).contains(&num) {
+                self.pump = num - 1;
+                machine.report(format!("Calibrating pump {}", num));
+                machine.play_melody(CONFIRM_SELECTION);
+            } else {
+                machine.play_melody(ERROR);
+            }
+        }
+        if state.just_pressed(Switch::Right) {
+            machine.pour_for(self.pump, 0, CALIBRATION_POUR_DURATION_MS);
+            machine.wait_for_all_actions();
+            machine.report("Type the poured volume in ml and press right".into());
+            self.state = CalibrationState::WaitForVolume;
+        }
+    }
+
+    fn wait_for_volume(&mut self, machine: &mut Machine, state: &State) {
+        self.typed_digits.extend(
+            state
+                .iter_just_pressed()
+                .filter_map(|switch| switch.get_num()),
+        );
+        if state.just_pressed(Switch::Right) {
+            let volume_ml = typed_num(&self.typed_digits);
+            self.typed_digits.clear();
+            if (1..=MAX_CALIBRATION_VOLUME_ML).contains(&volume_ml) {
+                let pump_config = &mut machine.get_config_mut().pumps[self.pump];
+                pump_config.flow_rate_ml_per_s =
+                    volume_ml as f32 * 1000.0 / CALIBRATION_POUR_DURATION_MS as f32;
+                let message = format!(
+                    "Flow rate of pump {}: {:.1} ml/s, one shot takes {} ms",
+                    self.pump + 1,
+                    pump_config.flow_rate_ml_per_s,
+                    pump_config.pour_duration(pump_config.shot_volume_ml)
+                );
+                machine.report(message);
+                machine.play_melody(CONFIRM_SELECTION);
+                self.state = CalibrationState::WaitForGlass;
+            } else {
+                machine.play_melody(ERROR);
+            }
+            machine.wait_for_all_actions();
+        }
+        if state.just_pressed(Switch::Left) {
+            self.typed_digits.clear();
+            self.state = CalibrationState::WaitForGlass;
+        }
+    }
+}
+
+impl Program for CalibrationProgram {
+    fn update(&mut self, machine: &mut Machine, state: &State) {
+        match self.state {
+            CalibrationState::WaitForGlass => self.wait_for_glass(machine, state),
+            CalibrationState::WaitForVolume => self.wait_for_volume(machine, state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CalibrationProgram;
+    use super::CALIBRATION_POUR_DURATION_MS;
+    use crate::hardware_interface::Switch;
+    use crate::machine::Machine;
+    use crate::programs::SimplePouring;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+
+    #[test]
+    fn the_flow_rate_is_derived_from_the_measured_volume() {
+        let mut machine = Machine::new();
+        let program = CalibrationProgram::new(&mut machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        sim.press(&[Switch::Number2]);
+        sim.press(&[Switch::Right]);
+        sim.run_for(CALIBRATION_POUR_DURATION_MS);
+        assert_eq!(sim.interface.num_pours, [0, 1, 0, 0]);
+        for switch in [
+            Switch::Number1,
+            Switch::Number0,
+            Switch::Number0,
+            Switch::Right,
+        ] {
+            sim.press(&[switch]);
+        }
+        let pump_config = sim.machine.config().pumps[1];
+        assert_eq!(pump_config.flow_rate_ml_per_s, 20.0);
+        assert_eq!(pump_config.pour_duration(pump_config.shot_volume_ml), 1000);
+    }
+
+    #[test]
+    fn overly_long_volumes_are_rejected() {
+        let mut machine = Machine::new();
+        let flow_rate_ml_per_s = machine.config().pumps[0].flow_rate_ml_per_s;
+        let program = CalibrationProgram::new(&mut machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        sim.press(&[Switch::Right]);
+        sim.run_for(CALIBRATION_POUR_DURATION_MS);
+        for _ in 0..30 {
+            sim.press(&[Switch::Number9]);
+        }
+        sim.press(&[Switch::Right]);
+        assert_eq!(
+            sim.machine.config().pumps[0].flow_rate_ml_per_s,
+            flow_rate_ml_per_s
+        );
+    }
+
+    #[test]
+    fn half_a_shot_takes_half_the_time() {
+        let mut sim = Simulation::new(SimplePouring);
+        sim.machine.get_config_mut().pumps[0].flow_rate_ml_per_s = 20.0;
+        sim.interface.pressed[Switch::Number0] = true;
+        let mut relay_on_ms = 0;
+        for _ in 0..1000 {
+            sim.run_for(STEP_MS);
+            sim.interface.pressed[Switch::Number0] = false;
+            if sim.interface.relay_on[0] {
+                relay_on_ms += STEP_MS;
+            }
+        }
+        assert_eq!(sim.interface.num_pours[0], 1);
+        assert!(relay_on_ms.abs_diff(500) <= 2 * STEP_MS);
+    }
+}
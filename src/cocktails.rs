@@ -0,0 +1,331 @@
+//! Cocktails mixed from the bottles of several pumps.
+//!
+//! Recipes are uploaded over the serial console, one per line:
+//!
+//! ```text
+//! screwdriver: 1 40, 2 120
+//! long_drink: 1 20 + 3 20, 2 100  # a comment
+//! ```
+//!
+//! Every ingredient is the number of a pump, starting from one, and the
+//! volume in ml. Ingredients joined by `+` are poured at the same time,
+//! the steps separated by `,` one after another.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::hardware_interface::State;
+use crate::hardware_interface::NUM_PUMPS;
+use crate::hardware_interface::STORAGE_SLOT_SIZE;
+use crate::machine::Machine;
+use crate::melody::ERROR;
+use crate::programs::Program;
+use crate::Duration;
+
+/// One for every number key.
+const MAX_NUM_RECIPES: usize = 10;
+/// Everything beyond this would not fit into a glass.
+const MAX_RECIPE_VOLUME_ML: f32 = 400.0;
+/// Lets the last drops of a step drip off before the next one.
+const DELAY_BETWEEN_STEPS_MS: Duration = 300;
+
+const UPLOAD_START: &str = "recipes";
+const UPLOAD_END: &str = "end recipes";
+
+#[derive(Debug, PartialEq)]
+pub struct RecipeError {
+    pub line: usize,
+    pub message: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ingredient {
+    pub pump: usize,
+    pub volume_ml: f32,
+}
+
+impl Ingredient {
+    fn parse(token: &str) -> Result<Self, &'static str> {
+        let [pump, volume_ml] = token.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err("expected a pump and a volume");
+        };
+        let pump: usize = pump
+            .parse()
+            .ok()
+            .filter(|pump| (1..=NUM_PUMPS).contains(pump))
+            .ok_or("unknown pump")?;
+        let volume_ml: f32 = volume_ml
+            .parse()
+            .ok()
+            .filter(|volume_ml| *volume_ml > 0.0)
+            .ok_or("invalid volume")?;
+        Ok(Self {
+            pump: pump - 1,
+            volume_ml,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recipe {
+    pub name: String,
+    /// The ingredients of a step are poured at the same time.
+    pub steps: Vec<Vec<Ingredient>>,
+}
+
+impl Recipe {
+    fn parse(line: &str) -> Result<Self, &'static str> {
+        let (name, steps) = line.split_once(':').ok_or("missing name")?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("missing name");
+        }
+        let steps = steps
+            .split(',')
+            .map(|step| step.split('+').map(Ingredient::parse).collect())
+            .collect::<Result<Vec<Vec<_>>, _>>()?;
+        for step in steps.iter() {
+            for (index, ingredient) in step.iter().enumerate() {
+                if step[..index]
+                    .iter()
+                    .any(|other| other.pump == ingredient.pump)
+                {
+                    return Err("pump used twice in one step");
+                }
+            }
+        }
+        let recipe = Self {
+            name: name.to_string(),
+            steps,
+        };
+        if recipe.total_volume_ml() > MAX_RECIPE_VOLUME_ML {
+            return Err("too much in total");
+        }
+        Ok(recipe)
+    }
+
+    pub fn total_volume_ml(&self) -> f32 {
+        self.steps
+            .iter()
+            .flatten()
+            .map(|ingredient| ingredient.volume_ml)
+            .sum()
+    }
+}
+
+pub fn parse_recipes(source: &str) -> Result<Vec<Recipe>, RecipeError> {
+    let mut recipes = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message| RecipeError {
+            line: index + 1,
+            message,
+        };
+        if recipes.len() == MAX_NUM_RECIPES {
+            return Err(error("too many recipes"));
+        }
+        recipes.push(Recipe::parse(line).map_err(error)?);
+    }
+    Ok(recipes)
+}
+
+/// Collects the recipes sent over the serial console, between
+/// `recipes` and `end recipes`. They replace all previous recipes.
+pub struct RecipeUpload {
+    lines: Vec<String>,
+}
+
+impl RecipeUpload {
+    pub fn start(line: &str) -> Option<Self> {
+        (line.trim() == UPLOAD_START).then(|| Self { lines: vec![] })
+    }
+
+    /// Returns true once the upload is complete.
+    pub fn add_line(&mut self, line: String) -> bool {
+        if line.trim() == UPLOAD_END {
+            true
+        } else {
+            self.lines.push(line);
+            false
+        }
+    }
+
+    /// The source and the parsed recipes, if they are valid.
+    pub fn finish(self) -> Result<(String, Vec<Recipe>), String> {
+        let source = self.lines.join("\n");
+        if source.len() > STORAGE_SLOT_SIZE {
+            return Err("Recipes too long".to_string());
+        }
+        let recipes = parse_recipes(&source)
+            .map_err(|err| format!("Recipe error in line {}: {}", err.line, err.message))?;
+        Ok((source, recipes))
+    }
+}
+
+/// Pour the steps one after another, timed by the calibration of the
+/// pumps.
+fn pour_recipe(machine: &mut Machine, recipe: &Recipe) {
+    let mut offset = 0;
+    for step in recipe.steps.iter() {
+        let mut step_duration = 0;
+        for ingredient in step {
+            machine.pour_volume(ingredient.pump, offset, ingredient.volume_ml);
+            let duration =
+                machine.config().pumps[ingredient.pump].pour_duration(ingredient.volume_ml);
+            step_duration = step_duration.max(duration);
+        }
+        offset += step_duration + DELAY_BETWEEN_STEPS_MS;
+    }
+}
+
+/// The number key of the recipe, the tenth one is on key 0.
+fn recipe_key(index: usize) -> usize {
+    (index + 1) % MAX_NUM_RECIPES
+}
+
+/// Mixes one of the uploaded recipes whenever its number key is
+/// pressed.
+pub struct CocktailProgram;
+
+impl CocktailProgram {
+    pub fn new(machine: &mut Machine) -> Self {
+        if machine.recipes().is_empty() {
+            machine.report("No recipes uploaded".into());
+            machine.play_melody_after_queued_actions(ERROR);
+        }
+        let menu: Vec<String> = machine
+            .recipes()
+            .iter()
+            .enumerate()
+            .map(|(index, recipe)| format!("{}: {}", recipe_key(index), recipe.name))
+            .collect();
+        for line in menu {
+            machine.report(line);
+        }
+        Self
+    }
+}
+
+impl Program for CocktailProgram {
+    fn update(&mut self, machine: &mut Machine, state: &State) {
+        let Some(key) = state.lowest_pressed_number_key() else {
+            return;
+        };
+        let index = (key + MAX_NUM_RECIPES - 1) % MAX_NUM_RECIPES;
+        match machine.recipes().get(index).cloned() {
+            Some(recipe) => {
+                machine.report(format!("Mixing {}", recipe.name));
+                pour_recipe(machine, &recipe);
+            }
+            None => machine.play_melody(ERROR),
+        }
+        machine.wait_for_all_actions();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::parse_recipes;
+    use super::CocktailProgram;
+    use super::Ingredient;
+    use super::RecipeError;
+    use crate::hardware_interface::StorageSlot;
+    use crate::hardware_interface::Switch;
+    use crate::hardware_interface::NUM_PUMPS;
+    use crate::machine::Machine;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+    use crate::Time;
+
+    #[test]
+    fn recipes_are_validated() {
+        let recipes =
+            parse_recipes("# Classics\nscrewdriver: 1 40, 2 120\nmix: 1 20 + 3 20").unwrap();
+        assert_eq!(recipes.len(), 2);
+        assert_eq!(recipes[0].name, "screwdriver");
+        assert_eq!(
+            recipes[1].steps,
+            [[
+                Ingredient {
+                    pump: 0,
+                    volume_ml: 20.0
+                },
+                Ingredient {
+                    pump: 2,
+                    volume_ml: 20.0
+                }
+            ]]
+        );
+        for (source, message) in [
+            ("a: 5 20", "unknown pump"),
+            ("a: 1 0", "invalid volume"),
+            ("a: 1 20 + 1 20", "pump used twice in one step"),
+            ("a: 1 300, 2 200", "too much in total"),
+            ("1 20", "missing name"),
+        ] {
+            assert_eq!(
+                parse_recipes(&["ok: 1 20", source].join("\n")).err(),
+                Some(RecipeError { line: 2, message })
+            );
+        }
+    }
+
+    #[test]
+    fn every_pump_pours_its_share() {
+        let mut machine = Machine::new();
+        machine.set_recipes(parse_recipes("mix: 1 40 + 2 20, 3 60").unwrap());
+        machine.get_config_mut().pumps[1].flow_rate_ml_per_s = 10.0;
+        let program = CocktailProgram::new(&mut machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        sim.interface.pressed[Switch::Number1] = true;
+        let mut relay_on_ms = [0; NUM_PUMPS];
+        let mut last_on_ms: [Option<Time>; NUM_PUMPS] = [None; NUM_PUMPS];
+        let mut first_on_ms: [Option<Time>; NUM_PUMPS] = [None; NUM_PUMPS];
+        for _ in 0..10_000 / STEP_MS {
+            sim.run_for(STEP_MS);
+            sim.interface.pressed[Switch::Number1] = false;
+            for pump in 0..NUM_PUMPS {
+                if sim.interface.relay_on[pump] {
+                    relay_on_ms[pump] += STEP_MS;
+                    first_on_ms[pump].get_or_insert(sim.interface.time_ms);
+                    last_on_ms[pump] = Some(sim.interface.time_ms);
+                }
+            }
+        }
+        let pumps = sim.machine.config().pumps;
+        for (pump, volume_ml) in [(0, 40.0), (1, 20.0), (2, 60.0), (3, 0.0)] {
+            let expected_ms = pumps[pump].pour_duration(volume_ml);
+            assert!(relay_on_ms[pump].abs_diff(expected_ms) <= 2 * STEP_MS);
+        }
+        assert_eq!(sim.interface.num_pours, [1, 1, 1, 0]);
+        // Both ingredients of the first step are poured at the same time.
+        assert_eq!(first_on_ms[0], first_on_ms[1]);
+        assert!(first_on_ms[2] > last_on_ms[0].max(last_on_ms[1]));
+    }
+
+    #[test]
+    fn uploaded_recipes_are_stored() {
+        let mut machine = Machine::new();
+        let program = CocktailProgram::new(&mut machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        for line in ["recipes", "shot: 1 20", "end recipes"] {
+            sim.interface.serial_lines.push_back(line.to_string());
+        }
+        sim.run_for(STEP_MS * 4);
+        assert_eq!(sim.machine.recipes()[0].name, "shot");
+        assert_eq!(
+            sim.interface.storage[&StorageSlot::Recipes.index()],
+            b"shot: 1 20"
+        );
+        sim.press(&[Switch::Number2]);
+        assert_eq!(sim.interface.num_pours, [0; NUM_PUMPS]);
+    }
+}
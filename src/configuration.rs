@@ -4,77 +4,380 @@ use core::ops::RangeInclusive;
 
 use crate::hardware_interface::State;
 use crate::hardware_interface::Switch;
+use crate::hardware_interface::NUM_PUMPS;
 use crate::machine::Machine;
 use crate::melody::CONFIRM_SELECTION;
 use crate::melody::ERROR;
 use crate::programs::Program;
+use crate::programs::ProgramEntry;
+use crate::programs::PROGRAMS;
 use crate::Duration;
-use crate::Time;
+
+pub const NUM_KEYS_PER_PAGE: usize = 10;
+const MAX_NUM_PROGRAM_PAGES: usize = 10;
 
 const DEFAULT_NUM_PLAYERS: usize = 2;
+const DEFAULT_LEFT_TEAM_BUTTONS: &[usize] = &[1, 4, 7];
+const DEFAULT_RIGHT_TEAM_BUTTONS: &[usize] = &[3, 6, 9];
+
+const DEFAULT_SHOT_VOLUME_ML: f32 = 20.0;
+/// The original pump poured a 20 ml shot in 700 ms.
+const DEFAULT_FLOW_RATE_ML_PER_S: f32 = 20.0 / 0.7;
+/// About half a metre of tube with an inner diameter of 5 mm.
+const DEFAULT_TUBE_VOLUME_ML: f32 = 10.0;
 
-const DURATION_MS_PER_SHOT: Time = 700;
+const DEFAULT_IDLE_TIMEOUT_MS: Duration = 5 * 60 * 1000;
+
+const SCRIPT_DEFAULT_MAX_NUM_SHOTS: usize = 10;
 
 const RUSSIAN_ROULETTE_DEFAULT_LOSS_PROBABILITY: f32 = 0.1;
 const RUSSIAN_ROULETTE_DEFAULT_MIN_NUM_SHOTS: usize = 4;
 const RUSSIAN_ROULETTE_DEFAULT_MAX_NUM_SHOTS: usize = 10;
+const RUSSIAN_ROULETTE_DEFAULT_NUM_CHAMBERS: usize = 6;
+const RUSSIAN_ROULETTE_DEFAULT_NUM_LOADED: usize = 1;
+const RUSSIAN_ROULETTE_DEFAULT_ESCALATION_STEP: f32 = 0.05;
+const RUSSIAN_ROULETTE_DEFAULT_MAX_LOSS_PROBABILITY: f32 = 0.5;
 
 const REACTION_DEFAULT_NUM_SHOTS_LOSER: usize = 5;
 const REACTION_DEFAULT_NUM_SHOTS_EARLY_START: usize = 10;
+const REACTION_DEFAULT_TIME_THRESHOLD_MS: Duration = 600;
+const REACTION_DEFAULT_TOURNAMENT_LENGTH: usize = 3;
+const REACTION_DEFAULT_TOURNAMENT_SHOT_ESCALATION: usize = 1;
+
+const SIMON_SAYS_DEFAULT_START_LENGTH: usize = 3;
+const SIMON_SAYS_DEFAULT_LENGTH_INCREMENT: usize = 1;
+const SIMON_SAYS_DEFAULT_INPUT_TIME_LIMIT_MS: Duration = 3000;
+const SIMON_SAYS_DEFAULT_MAX_NUM_SHOTS: usize = 5;
+
+/// The buttons of the players of each team.
+#[derive(Debug, Clone)]
+pub struct TeamLayout {
+    pub teams: Vec<Vec<usize>>,
+}
+
+impl TeamLayout {
+    /// Split the players between the left and the right column of
+    /// the keypad, with at most three players per team.
+    fn from_num_players(num_players: usize) -> Self {
+        let num_left = (num_players / 2).min(DEFAULT_LEFT_TEAM_BUTTONS.len());
+        let num_right = (num_players - num_players / 2).min(DEFAULT_RIGHT_TEAM_BUTTONS.len());
+        Self {
+            teams: [
+                &DEFAULT_LEFT_TEAM_BUTTONS[..num_left],
+                &DEFAULT_RIGHT_TEAM_BUTTONS[..num_right],
+            ]
+            .iter()
+            .filter(|buttons| !buttons.is_empty())
+            .map(|buttons| buttons.to_vec())
+            .collect(),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let mut buttons: Vec<usize> = self.teams.iter().flatten().copied().collect();
+        let num_buttons = buttons.len();
+        buttons.sort();
+        buttons.dedup();
+        self.teams.len() >= 2
+            && self.teams.iter().all(|team| !team.is_empty())
+            && buttons.len() == num_buttons
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TournamentFormat {
+    /// The tournament ends after a fixed number of rounds.
+    BestOf,
+    /// The tournament ends once somebody reaches a number of points.
+    FirstTo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RussianRouletteMode {
+    /// Every pull has the same loss probability.
+    #[default]
+    Classic,
+    /// A cylinder with a fixed number of loaded chambers.
+    Revolver,
+    /// The loss probability increases with every safe pull.
+    Escalating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EscalationCurve {
+    /// The step is added to the probability after every safe pull.
+    #[default]
+    Linear,
+    /// The probability is multiplied by one plus the step after every safe pull.
+    Geometric,
+}
+
+/// The shot size, the calibration and the bottle of one pump.
+#[derive(Debug, Clone, Copy)]
+pub struct PumpConfiguration {
+    pub shot_volume_ml: f32,
+    /// How much the pump pours per second, measured by the calibration
+    /// program.
+    pub flow_rate_ml_per_s: f32,
+    /// The level of the bottle is only tracked if its capacity is known.
+    pub bottle_capacity_ml: Option<f32>,
+    /// How much fits into the tube from the bottle to the outlet.
+    pub tube_volume_ml: f32,
+}
+
+impl Default for PumpConfiguration {
+    fn default() -> Self {
+        Self {
+            shot_volume_ml: DEFAULT_SHOT_VOLUME_ML,
+            flow_rate_ml_per_s: DEFAULT_FLOW_RATE_ML_PER_S,
+            bottle_capacity_ml: None,
+            tube_volume_ml: DEFAULT_TUBE_VOLUME_ML,
+        }
+    }
+}
+
+impl PumpConfiguration {
+    /// How long the relay has to be on to pour the volume.
+    pub fn pour_duration(&self, volume_ml: f32) -> Duration {
+        (volume_ml / self.flow_rate_ml_per_s * 1000.0) as Duration
+    }
+
+    /// How much the pump pours while the relay is on for the duration.
+    pub fn volume_poured_in(&self, duration: Duration) -> f32 {
+        duration as f32 * self.flow_rate_ml_per_s / 1000.0
+    }
+}
 
 #[derive(Debug)]
 pub struct Configuration {
+    /// The keys of the program menu and the ids of the programs they select.
+    pub program_keys: Vec<(usize, &'static str)>,
     pub num_players: usize,
-    pub shot_duration: Duration,
+    pub reaction_custom_team_layout: Option<TeamLayout>,
+    pub pumps: [PumpConfiguration; NUM_PUMPS],
+    /// The pump which the games pour the shots of the losers from.
+    pub game_pump: usize,
     pub russian_roulette_loss_probability: f32,
     pub russian_roulette_min_num_shots: usize,
     pub russian_roulette_max_num_shots: usize,
+    pub russian_roulette_mode: RussianRouletteMode,
+    pub russian_roulette_num_chambers: usize,
+    pub russian_roulette_num_loaded: usize,
+    pub russian_roulette_escalation_curve: EscalationCurve,
+    pub russian_roulette_escalation_step: f32,
+    pub russian_roulette_max_loss_probability: f32,
     pub reaction_num_shots_loser: usize,
     pub reaction_num_shots_early_start: usize,
+    pub reaction_time_threshold: Duration,
+    pub reaction_tournament_format: Option<TournamentFormat>,
+    pub reaction_tournament_length: usize,
+    pub reaction_tournament_shot_escalation: usize,
+    pub simon_says_start_length: usize,
+    pub simon_says_length_increment: usize,
+    pub simon_says_input_time_limit: Duration,
+    pub simon_says_max_num_shots: usize,
+    /// Time without any key press after which the attract mode starts.
+    /// Zero disables the attract mode.
+    pub idle_timeout: Duration,
+    /// The number of shots a script may pour per run.
+    pub script_max_num_shots: usize,
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
+            program_keys: PROGRAMS
+                .iter()
+                .filter_map(|entry| entry.default_key.map(|key| (key, entry.id)))
+                .collect(),
             num_players: DEFAULT_NUM_PLAYERS,
-            shot_duration: DURATION_MS_PER_SHOT,
+            reaction_custom_team_layout: None,
+            pumps: [PumpConfiguration::default(); NUM_PUMPS],
+            game_pump: 0,
             russian_roulette_loss_probability: RUSSIAN_ROULETTE_DEFAULT_LOSS_PROBABILITY,
             russian_roulette_min_num_shots: RUSSIAN_ROULETTE_DEFAULT_MIN_NUM_SHOTS,
             russian_roulette_max_num_shots: RUSSIAN_ROULETTE_DEFAULT_MAX_NUM_SHOTS,
+            russian_roulette_mode: RussianRouletteMode::default(),
+            russian_roulette_num_chambers: RUSSIAN_ROULETTE_DEFAULT_NUM_CHAMBERS,
+            russian_roulette_num_loaded: RUSSIAN_ROULETTE_DEFAULT_NUM_LOADED,
+            russian_roulette_escalation_curve: EscalationCurve::default(),
+            russian_roulette_escalation_step: RUSSIAN_ROULETTE_DEFAULT_ESCALATION_STEP,
+            russian_roulette_max_loss_probability: RUSSIAN_ROULETTE_DEFAULT_MAX_LOSS_PROBABILITY,
             reaction_num_shots_loser: REACTION_DEFAULT_NUM_SHOTS_LOSER,
             reaction_num_shots_early_start: REACTION_DEFAULT_NUM_SHOTS_EARLY_START,
+            reaction_time_threshold: REACTION_DEFAULT_TIME_THRESHOLD_MS,
+            reaction_tournament_format: None,
+            reaction_tournament_length: REACTION_DEFAULT_TOURNAMENT_LENGTH,
+            reaction_tournament_shot_escalation: REACTION_DEFAULT_TOURNAMENT_SHOT_ESCALATION,
+            simon_says_start_length: SIMON_SAYS_DEFAULT_START_LENGTH,
+            simon_says_length_increment: SIMON_SAYS_DEFAULT_LENGTH_INCREMENT,
+            simon_says_input_time_limit: SIMON_SAYS_DEFAULT_INPUT_TIME_LIMIT_MS,
+            simon_says_max_num_shots: SIMON_SAYS_DEFAULT_MAX_NUM_SHOTS,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT_MS,
+            script_max_num_shots: SCRIPT_DEFAULT_MAX_NUM_SHOTS,
         }
     }
 }
 
-enum Variable {
+impl Configuration {
+    pub fn program_for_key(&self, key: usize) -> Option<&'static ProgramEntry> {
+        let (_, id) = self.program_keys.iter().find(|(other, _)| *other == key)?;
+        PROGRAMS.iter().find(|entry| entry.id == *id)
+    }
+
+    fn set_program_key(&mut self, key: usize, program: Option<&'static ProgramEntry>) {
+        self.program_keys.retain(|(other, _)| *other != key);
+        if let Some(entry) = program {
+            self.program_keys.push((key, entry.id));
+        }
+    }
+
+    pub fn reaction_team_layout(&self) -> TeamLayout {
+        self.reaction_custom_team_layout
+            .clone()
+            .unwrap_or_else(|| TeamLayout::from_num_players(self.num_players))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variable {
     ReactionNumberOfPlayers,
-    DelayPerShot,
+    ShotVolume,
     RussianRouletteLossProbability,
     RussianRouletteMinNumberOfShots,
     RussianRouletteMaxNumberOfShots,
     ReactionNumShotsLoser,
     ReactionNumShotsEarlyStart,
+    SimonSaysStartLength,
+    SimonSaysLengthIncrement,
+    SimonSaysInputTimeLimit,
+    SimonSaysMaxNumShots,
+    ReactionTimeThreshold,
+    ReactionTeamLayout,
+    ReactionTournamentFormat,
+    ReactionTournamentLength,
+    ReactionTournamentShotEscalation,
+    RussianRouletteMode,
+    RussianRouletteNumChambers,
+    RussianRouletteNumLoaded,
+    RussianRouletteEscalationCurve,
+    RussianRouletteEscalationStep,
+    RussianRouletteMaxLossProbability,
+    ProgramKey,
+    IdleTimeout,
+    ScriptMaxNumShots,
+    PumpShotVolume,
+    GamePump,
+    PumpBottleCapacity,
+    NewBottle,
+    PumpTubeVolume,
 }
 
 impl Variable {
+    fn from_num(num: usize) -> Option<Self> {
+        match num {
+            1 => Some(Variable::ShotVolume),
+            2 => Some(Variable::RussianRouletteLossProbability),
+            3 => Some(Variable::RussianRouletteMinNumberOfShots),
+            4 => Some(Variable::RussianRouletteMaxNumberOfShots),
+            5 => Some(Variable::ReactionNumberOfPlayers),
+            6 => Some(Variable::ReactionNumShotsLoser),
+            7 => Some(Variable::ReactionNumShotsEarlyStart),
+            8 => Some(Variable::SimonSaysStartLength),
+            9 => Some(Variable::SimonSaysLengthIncrement),
+            10 => Some(Variable::SimonSaysInputTimeLimit),
+            11 => Some(Variable::SimonSaysMaxNumShots),
+            12 => Some(Variable::ReactionTimeThreshold),
+            13 => Some(Variable::ReactionTeamLayout),
+            14 => Some(Variable::ReactionTournamentFormat),
+            15 => Some(Variable::ReactionTournamentLength),
+            16 => Some(Variable::ReactionTournamentShotEscalation),
+            17 => Some(Variable::RussianRouletteMode),
+            18 => Some(Variable::RussianRouletteNumChambers),
+            19 => Some(Variable::RussianRouletteNumLoaded),
+            20 => Some(Variable::RussianRouletteEscalationCurve),
+            21 => Some(Variable::RussianRouletteEscalationStep),
+            22 => Some(Variable::RussianRouletteMaxLossProbability),
+            23 => Some(Variable::ProgramKey),
+            24 => Some(Variable::IdleTimeout),
+            25 => Some(Variable::ScriptMaxNumShots),
+            26 => Some(Variable::PumpShotVolume),
+            27 => Some(Variable::GamePump),
+            28 => Some(Variable::PumpBottleCapacity),
+            29 => Some(Variable::NewBottle),
+            30 => Some(Variable::PumpTubeVolume),
+            _ => None,
+        }
+    }
+
+    pub fn num(&self) -> usize {
+        (1..)
+            .find(|num| Variable::from_num(*num) == Some(*self))
+            .unwrap()
+    }
+
     fn acceptable_range(&self) -> RangeInclusive<usize> {
         match self {
             Variable::ReactionNumberOfPlayers => 1..=9,
-            Variable::DelayPerShot => 100..=2000,
+            // In ml.
+            Variable::ShotVolume => 1..=500,
             Variable::RussianRouletteLossProbability => 0..=100,
             Variable::RussianRouletteMinNumberOfShots => 1..=80,
             Variable::RussianRouletteMaxNumberOfShots => 1..=80,
             Variable::ReactionNumShotsLoser => 1..=80,
             Variable::ReactionNumShotsEarlyStart => 1..=80,
+            Variable::SimonSaysStartLength => 1..=20,
+            Variable::SimonSaysLengthIncrement => 1..=5,
+            Variable::SimonSaysInputTimeLimit => 500..=20000,
+            Variable::SimonSaysMaxNumShots => 1..=80,
+            Variable::ReactionTimeThreshold => 100..=5000,
+            // The buttons of the players, see `wait_for_team_layout`.
+            Variable::ReactionTeamLayout => 0..=9,
+            Variable::ReactionTournamentFormat => 0..=2,
+            Variable::ReactionTournamentLength => 1..=20,
+            Variable::ReactionTournamentShotEscalation => 0..=10,
+            Variable::RussianRouletteMode => 0..=2,
+            Variable::RussianRouletteNumChambers => 2..=20,
+            Variable::RussianRouletteNumLoaded => 1..=19,
+            Variable::RussianRouletteEscalationCurve => 0..=1,
+            Variable::RussianRouletteEscalationStep => 0..=100,
+            Variable::RussianRouletteMaxLossProbability => 0..=100,
+            // The key in the program menu, see `wait_for_program_key`.
+            Variable::ProgramKey => 0..=NUM_KEYS_PER_PAGE * MAX_NUM_PROGRAM_PAGES - 1,
+            // In seconds.
+            Variable::IdleTimeout => 0..=3600,
+            Variable::ScriptMaxNumShots => 0..=80,
+            // In ml, for the pump selected first, see `wait_for_pump_setting`.
+            Variable::PumpShotVolume => 1..=500,
+            // Pumps are numbered from one here.
+            Variable::GamePump => 1..=NUM_PUMPS,
+            // In ml, for the pump selected first. Zero stops tracking the
+            // level of the bottle.
+            Variable::PumpBottleCapacity => 0..=5000,
+            // The pump whose bottle was replaced.
+            Variable::NewBottle => 1..=NUM_PUMPS,
+            // In ml, for the pump selected first.
+            Variable::PumpTubeVolume => 0..=200,
         }
     }
 }
 
+/// The number typed on the number keys. Saturates instead of
+/// overflowing if someone keeps typing, so that the range checks reject
+/// it.
+pub fn typed_num(digits: &[usize]) -> usize {
+    digits.iter().fold(0, |num: usize, digit| {
+        num.saturating_mul(10).saturating_add(*digit)
+    })
+}
+
 #[derive(Default)]
 pub struct ConfigurationProgram {
     selected_variable: Option<Variable>,
     typed_digits: Vec<usize>,
+    typed_teams: Vec<Vec<usize>>,
+    typed_program_key: Option<usize>,
+    typed_pump: Option<usize>,
 }
 
 impl ConfigurationProgram {
@@ -84,8 +387,8 @@ impl ConfigurationProgram {
             Variable::ReactionNumberOfPlayers => {
                 config.num_players = num;
             }
-            Variable::DelayPerShot => {
-                config.shot_duration = num as u32;
+            Variable::ShotVolume => {
+                config.pumps[config.game_pump].shot_volume_ml = num as f32;
             }
             Variable::RussianRouletteLossProbability => {
                 config.russian_roulette_loss_probability = num as f32 / 100.0;
@@ -102,21 +405,96 @@ impl ConfigurationProgram {
             Variable::ReactionNumShotsEarlyStart => {
                 config.reaction_num_shots_early_start = num;
             }
+            Variable::SimonSaysStartLength => {
+                config.simon_says_start_length = num;
+            }
+            Variable::SimonSaysLengthIncrement => {
+                config.simon_says_length_increment = num;
+            }
+            Variable::SimonSaysInputTimeLimit => {
+                config.simon_says_input_time_limit = num as Duration;
+            }
+            Variable::SimonSaysMaxNumShots => {
+                config.simon_says_max_num_shots = num;
+            }
+            Variable::ReactionTimeThreshold => {
+                config.reaction_time_threshold = num as Duration;
+            }
+            // The layout consists of more than one number, see `wait_for_team_layout`.
+            Variable::ReactionTeamLayout => {}
+            Variable::ReactionTournamentFormat => {
+                config.reaction_tournament_format = match num {
+                    1 => Some(TournamentFormat::BestOf),
+                    2 => Some(TournamentFormat::FirstTo),
+                    _ => None,
+                };
+            }
+            Variable::ReactionTournamentLength => {
+                config.reaction_tournament_length = num;
+            }
+            Variable::ReactionTournamentShotEscalation => {
+                config.reaction_tournament_shot_escalation = num;
+            }
+            Variable::RussianRouletteMode => {
+                config.russian_roulette_mode = match num {
+                    1 => RussianRouletteMode::Revolver,
+                    2 => RussianRouletteMode::Escalating,
+                    _ => RussianRouletteMode::Classic,
+                };
+            }
+            Variable::RussianRouletteNumChambers => {
+                config.russian_roulette_num_chambers = num;
+            }
+            Variable::RussianRouletteNumLoaded => {
+                config.russian_roulette_num_loaded = num;
+            }
+            Variable::RussianRouletteEscalationCurve => {
+                config.russian_roulette_escalation_curve = match num {
+                    1 => EscalationCurve::Geometric,
+                    _ => EscalationCurve::Linear,
+                };
+            }
+            Variable::RussianRouletteEscalationStep => {
+                config.russian_roulette_escalation_step = num as f32 / 100.0;
+            }
+            Variable::RussianRouletteMaxLossProbability => {
+                config.russian_roulette_max_loss_probability = num as f32 / 100.0;
+            }
+            // The program key consists of two numbers, see `wait_for_program_key`.
+            Variable::ProgramKey => {}
+            Variable::IdleTimeout => {
+                config.idle_timeout = num as Duration * 1000;
+            }
+            Variable::ScriptMaxNumShots => {
+                config.script_max_num_shots = num;
+            }
+            // The pump is typed in first, see `wait_for_pump_setting`.
+            Variable::PumpShotVolume | Variable::PumpBottleCapacity | Variable::PumpTubeVolume => {}
+            Variable::GamePump => {
+                config.game_pump = num - 1;
+            }
+            Variable::NewBottle => {
+                machine.new_bottle(num - 1);
+            }
         }
     }
 
-    fn wait_for_setting(&mut self, machine: &mut Machine, state: &State) {
-        let selected_variable = self.selected_variable.as_ref().unwrap();
+    fn read_digits(&mut self, state: &State) {
         for switch in state.iter_just_pressed() {
             if let Some(num) = switch.get_num() {
                 self.typed_digits.push(num);
             }
         }
+    }
+
+    fn wait_for_setting(&mut self, machine: &mut Machine, state: &State) {
+        self.read_digits(state);
+        let selected_variable = self.selected_variable.as_ref().unwrap();
         if state.just_pressed(Switch::Right) {
             let num = self.get_typed_num();
             if selected_variable.acceptable_range().contains(&num) {
                 machine.play_melody(CONFIRM_SELECTION);
-                self.configure(machine, &selected_variable, num);
+                self.configure(machine, selected_variable, num);
                 self.reset();
             } else {
                 machine.play_melody(ERROR);
@@ -129,47 +507,172 @@ impl ConfigurationProgram {
         }
     }
 
+    /// The buttons of each team are typed in and confirmed with the
+    /// right key. Confirming an empty team finishes the layout.
+    /// Finishing without any teams restores the default layout.
+    fn wait_for_team_layout(&mut self, machine: &mut Machine, state: &State) {
+        self.read_digits(state);
+        if state.just_pressed(Switch::Right) {
+            if !self.typed_digits.is_empty() {
+                self.typed_teams
+                    .push(core::mem::take(&mut self.typed_digits));
+                machine.play_melody(CONFIRM_SELECTION);
+            } else {
+                let layout = TeamLayout {
+                    teams: core::mem::take(&mut self.typed_teams),
+                };
+                if layout.teams.is_empty() {
+                    machine.play_melody(CONFIRM_SELECTION);
+                    machine.get_config_mut().reaction_custom_team_layout = None;
+                } else if layout.is_valid() {
+                    machine.play_melody(CONFIRM_SELECTION);
+                    machine.get_config_mut().reaction_custom_team_layout = Some(layout);
+                } else {
+                    machine.play_melody(ERROR);
+                }
+                self.reset();
+            }
+            machine.wait_for_all_actions();
+        }
+        if state.just_pressed(Switch::Left) {
+            self.reset();
+        }
+    }
+
+    /// First the key is typed in and confirmed with the right key,
+    /// then the number of the program in the list of all programs.
+    /// Program number zero removes the program from the key.
+    fn wait_for_program_key(&mut self, machine: &mut Machine, state: &State) {
+        self.read_digits(state);
+        if state.just_pressed(Switch::Right) {
+            let num = self.get_typed_num();
+            self.typed_digits = vec![];
+            match self.typed_program_key {
+                None if Variable::ProgramKey.acceptable_range().contains(&num) => {
+                    machine.play_melody(CONFIRM_SELECTION);
+                    self.typed_program_key = Some(num);
+                }
+                Some(key) if num <= PROGRAMS.len() => {
+                    machine.play_melody(CONFIRM_SELECTION);
+                    let program = num.checked_sub(1).map(|index| &PROGRAMS[index]);
+                    machine.get_config_mut().set_program_key(key, program);
+                    self.reset();
+                }
+                _ => {
+                    machine.play_melody(ERROR);
+                    self.reset();
+                }
+            }
+            machine.wait_for_all_actions();
+        }
+        if state.just_pressed(Switch::Left) {
+            self.reset();
+        }
+    }
+
+    /// First the number of the pump is typed in and confirmed with the
+    /// right key, then the setting of that pump.
+    fn wait_for_pump_setting(&mut self, machine: &mut Machine, state: &State) {
+        self.read_digits(state);
+        let selected_variable = self.selected_variable.unwrap();
+        if state.just_pressed(Switch::Right) {
+            let num = self.get_typed_num();
+            self.typed_digits = vec![];
+            match self.typed_pump {
+                None if (1..=NUM_PUMPS).contains(&num) => {
+                    machine.play_melody(CONFIRM_SELECTION);
+                    self.typed_pump = Some(num - 1);
+                }
+                Some(pump) if selected_variable.acceptable_range().contains(&num) => {
+                    machine.play_melody(CONFIRM_SELECTION);
+                    Self::configure_pump(machine, selected_variable, pump, num);
+                    self.reset();
+                }
+                _ => {
+                    machine.play_melody(ERROR);
+                    self.reset();
+                }
+            }
+            machine.wait_for_all_actions();
+        }
+        if state.just_pressed(Switch::Left) {
+            self.reset();
+        }
+    }
+
+    fn configure_pump(machine: &mut Machine, selected_variable: Variable, pump: usize, num: usize) {
+        let pump_config = &mut machine.get_config_mut().pumps[pump];
+        match selected_variable {
+            Variable::PumpShotVolume => pump_config.shot_volume_ml = num as f32,
+            // Configuring the capacity means that a full bottle is in place.
+            Variable::PumpBottleCapacity => {
+                pump_config.bottle_capacity_ml = (num > 0).then_some(num as f32);
+                machine.new_bottle(pump);
+            }
+            Variable::PumpTubeVolume => pump_config.tube_volume_ml = num as f32,
+            _ => unreachable!(),
+        }
+    }
+
     fn reset(&mut self) {
         self.typed_digits = vec![];
+        self.typed_teams = vec![];
+        self.typed_program_key = None;
+        self.typed_pump = None;
         self.selected_variable = None;
     }
 
     fn get_typed_num(&self) -> usize {
-        let mut factor = 1;
-        let mut sum = 0;
-        for digit in self.typed_digits.iter().rev() {
-            sum += digit * factor;
-            factor *= 10;
-        }
-        sum
+        typed_num(&self.typed_digits)
     }
 
     fn wait_for_variable_selection(&mut self, machine: &mut Machine, state: &State) {
-        if let Some(num) = state.lowest_pressed_number_key() {
-            self.selected_variable = match num {
-                1 => Some(Variable::DelayPerShot),
-                2 => Some(Variable::RussianRouletteLossProbability),
-                3 => Some(Variable::RussianRouletteMinNumberOfShots),
-                4 => Some(Variable::RussianRouletteMaxNumberOfShots),
-                5 => Some(Variable::ReactionNumberOfPlayers),
-                6 => Some(Variable::ReactionNumShotsLoser),
-                7 => Some(Variable::ReactionNumShotsEarlyStart),
-                _ => None,
-            };
+        self.read_digits(state);
+        if state.just_pressed(Switch::Right) {
+            self.selected_variable = Variable::from_num(self.get_typed_num());
             if self.selected_variable.is_some() {
                 machine.play_melody(CONFIRM_SELECTION);
-                machine.wait_for_all_actions();
+            } else {
+                machine.play_melody(ERROR);
             }
+            self.typed_digits = vec![];
+            machine.wait_for_all_actions();
+        }
+        if state.just_pressed(Switch::Left) {
+            self.reset();
         }
     }
 }
 
 impl Program for ConfigurationProgram {
     fn update(&mut self, machine: &mut Machine, state: &State) {
-        if self.selected_variable.is_some() {
+        if matches!(self.selected_variable, Some(Variable::ReactionTeamLayout)) {
+            self.wait_for_team_layout(machine, state);
+        } else if matches!(self.selected_variable, Some(Variable::ProgramKey)) {
+            self.wait_for_program_key(machine, state);
+        } else if matches!(
+            self.selected_variable,
+            Some(
+                Variable::PumpShotVolume | Variable::PumpBottleCapacity | Variable::PumpTubeVolume
+            )
+        ) {
+            self.wait_for_pump_setting(machine, state);
+        } else if self.selected_variable.is_some() {
             self.wait_for_setting(machine, state);
         } else {
             self.wait_for_variable_selection(machine, state);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::typed_num;
+
+    #[test]
+    fn typed_numbers_saturate() {
+        assert_eq!(typed_num(&[]), 0);
+        assert_eq!(typed_num(&[0, 4, 2]), 42);
+        assert_eq!(typed_num(&[9; 30]), usize::MAX);
+    }
+}
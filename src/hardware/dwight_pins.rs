@@ -1,37 +1,55 @@
-use bsp::hal::gpio::bank0::Gpio0;
 use bsp::hal::gpio::bank0::Gpio1;
-use bsp::hal::gpio::bank0::Gpio12;
-use bsp::hal::gpio::bank0::Gpio13;
 use bsp::hal::gpio::bank0::Gpio14;
 use bsp::hal::gpio::bank0::Gpio15;
+use bsp::hal::gpio::bank0::Gpio16;
+use bsp::hal::gpio::bank0::Gpio17;
+#[cfg(feature = "flow-meter")]
+use bsp::hal::gpio::bank0::Gpio18;
+#[cfg(feature = "glass-sensor")]
+use bsp::hal::gpio::bank0::Gpio19;
 use bsp::hal::gpio::DynPinId;
 use bsp::hal::gpio::FunctionNull;
 use bsp::hal::gpio::FunctionSioInput;
 use bsp::hal::gpio::FunctionSioOutput;
+use bsp::hal::gpio::FunctionUart;
 use bsp::hal::gpio::Pin;
 use bsp::hal::gpio::Pins;
 use bsp::hal::gpio::PullDown;
 use bsp::hal::gpio::PullUp;
+use dwight::hardware_interface::Switch;
+use dwight::hardware_interface::NUM_PUMPS;
 use rp_pico as bsp;
 
-type NumberSwitchPin = Pin<DynPinId, FunctionSioInput, PullUp>;
-type LeftSwitchPin = Pin<Gpio12, FunctionSioInput, PullUp>;
-type RightSwitchPin = Pin<Gpio13, FunctionSioInput, PullUp>;
+pub type SwitchPin = Pin<DynPinId, FunctionSioInput, PullUp>;
 
 type SpeakerPin = Pin<Gpio1, FunctionNull, PullDown>;
-type RelayPin = Pin<Gpio0, FunctionSioOutput, PullDown>;
+type RelayPin = Pin<DynPinId, FunctionSioOutput, PullDown>;
 type LeftLedPin = Pin<Gpio14, FunctionNull, PullDown>;
 type RightLedPin = Pin<Gpio15, FunctionNull, PullDown>;
+pub type UartPins = (
+    Pin<Gpio16, FunctionUart, PullDown>,
+    Pin<Gpio17, FunctionUart, PullDown>,
+);
+#[cfg(feature = "flow-meter")]
+pub type FlowMeterPin = Pin<Gpio18, FunctionSioInput, PullUp>;
+#[cfg(feature = "glass-sensor")]
+pub type GlassSensorPin = Pin<Gpio19, FunctionSioInput, PullUp>;
 
 pub struct DwightPins {
-    pub number_switches: [NumberSwitchPin; 10],
-    pub left_switch: LeftSwitchPin,
-    pub right_switch: RightSwitchPin,
+    pub number_switches: [SwitchPin; 10],
+    pub left_switch: SwitchPin,
+    pub right_switch: SwitchPin,
 
     speaker_pin: Option<SpeakerPin>,
-    pub relay_pin: RelayPin,
+    /// Indexed by the number of the pump.
+    pub relay_pins: [RelayPin; NUM_PUMPS],
     pub left_led: Option<LeftLedPin>,
     pub right_led: Option<RightLedPin>,
+    uart_pins: Option<UartPins>,
+    #[cfg(feature = "flow-meter")]
+    pub flow_meter: FlowMeterPin,
+    #[cfg(feature = "glass-sensor")]
+    pub glass_sensor: GlassSensorPin,
 }
 
 impl DwightPins {
@@ -50,12 +68,22 @@ impl DwightPins {
                 pins.gpio9.into_pull_up_input().into_dyn_pin(),
                 pins.gpio10.into_pull_up_input().into_dyn_pin(),
             ],
-            left_switch: pins.gpio12.into_pull_up_input(),
-            right_switch: pins.gpio13.into_pull_up_input(),
+            left_switch: pins.gpio12.into_pull_up_input().into_dyn_pin(),
+            right_switch: pins.gpio13.into_pull_up_input().into_dyn_pin(),
             speaker_pin: Some(pins.gpio1),
-            relay_pin: pins.gpio0.into_push_pull_output(),
+            relay_pins: [
+                pins.gpio0.into_push_pull_output().into_dyn_pin(),
+                pins.gpio20.into_push_pull_output().into_dyn_pin(),
+                pins.gpio21.into_push_pull_output().into_dyn_pin(),
+                pins.gpio22.into_push_pull_output().into_dyn_pin(),
+            ],
             left_led: Some(pins.gpio14),
             right_led: Some(pins.gpio15),
+            uart_pins: Some((pins.gpio16.into_function(), pins.gpio17.into_function())),
+            #[cfg(feature = "flow-meter")]
+            flow_meter: pins.gpio18.into_pull_up_input(),
+            #[cfg(feature = "glass-sensor")]
+            glass_sensor: pins.gpio19.into_pull_up_input(),
         }
     }
 
@@ -63,6 +91,24 @@ impl DwightPins {
         self.speaker_pin.take().unwrap()
     }
 
+    pub fn switch_pin(&self, switch: Switch) -> &SwitchPin {
+        match switch {
+            Switch::Left => &self.left_switch,
+            Switch::Right => &self.right_switch,
+            _ => &self.number_switches[switch.get_num().unwrap()],
+        }
+    }
+
+    pub fn switch_pins(&mut self) -> impl Iterator<Item = &mut SwitchPin> {
+        self.number_switches
+            .iter_mut()
+            .chain([&mut self.left_switch, &mut self.right_switch])
+    }
+
+    pub fn uart_pins(&mut self) -> UartPins {
+        self.uart_pins.take().unwrap()
+    }
+
     pub fn led_pins(&mut self) -> (LeftLedPin, RightLedPin) {
         (
             self.left_led.take().unwrap(),
@@ -0,0 +1,92 @@
+use core::cell::RefCell;
+
+use bsp::hal::gpio::Interrupt as GpioInterrupt;
+use bsp::hal::pac;
+use bsp::hal::pac::interrupt;
+use bsp::hal::Timer;
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::NVIC;
+use dwight::hardware_interface::SwitchEdges;
+use dwight::hardware_interface::SwitchState;
+use rp_pico as bsp;
+
+use crate::dwight_pins::SwitchPin;
+
+const NUM_GPIOS: usize = 30;
+const GPIOS_PER_REGISTER: usize = 8;
+const EDGE_LOW_BIT: usize = 2;
+const EDGE_HIGH_BIT: usize = 3;
+pub const FLOW_METER_GPIO: usize = 18;
+
+/// The timer counter at the first edges of every pin since the edges
+/// were last taken. Pressing pulls a switch pin low.
+struct Edges {
+    timer: Option<Timer>,
+    switches: [SwitchEdges; NUM_GPIOS],
+    flow_meter_pulses: u32,
+}
+
+static EDGES: Mutex<RefCell<Edges>> = Mutex::new(RefCell::new(Edges {
+    timer: None,
+    switches: [SwitchEdges::new(); NUM_GPIOS],
+    flow_meter_pulses: 0,
+}));
+
+/// Timestamps switch edges in the GPIO interrupt, so that the time of
+/// a press does not depend on how long the main loop takes. The same
+/// interrupt counts the pulses of the flow meter.
+pub fn start_capturing<'a>(timer: Timer, pins: impl Iterator<Item = &'a mut SwitchPin>) {
+    cortex_m::interrupt::free(|cs| EDGES.borrow(cs).borrow_mut().timer = Some(timer));
+    for pin in pins {
+        pin.set_interrupt_enabled(GpioInterrupt::EdgeLow, true);
+        pin.set_interrupt_enabled(GpioInterrupt::EdgeHigh, true);
+    }
+    // Safety: the handler only touches state behind the mutex.
+    unsafe { NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0) };
+}
+
+/// The timer counter at the first edge of the pin towards the state
+/// since the last call. Edges towards the other state are dropped.
+pub fn take(pin: &SwitchPin, state: SwitchState) -> Option<u64> {
+    let gpio = pin.id().num as usize;
+    cortex_m::interrupt::free(|cs| EDGES.borrow(cs).borrow_mut().switches[gpio].take(state))
+}
+
+/// The number of pulses of the flow meter since boot.
+#[cfg(feature = "flow-meter")]
+pub fn flow_meter_pulses() -> u32 {
+    cortex_m::interrupt::free(|cs| EDGES.borrow(cs).borrow().flow_meter_pulses)
+}
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    // Safety: the registers are only accessed here after setup, and
+    // writing a status bit merely acknowledges that edge.
+    let io_bank0 = unsafe { &*pac::IO_BANK0::ptr() };
+    cortex_m::interrupt::free(|cs| {
+        let mut edges = EDGES.borrow(cs).borrow_mut();
+        let Some(ticks) = edges.timer.map(|timer| timer.get_counter().ticks()) else {
+            return;
+        };
+        for (register, status) in io_bank0.proc0_ints.iter().enumerate() {
+            let bits = status.read().bits();
+            if bits == 0 {
+                continue;
+            }
+            io_bank0.intr[register].write(|w| unsafe { w.bits(bits) });
+            for pin in 0..GPIOS_PER_REGISTER {
+                let gpio = register * GPIOS_PER_REGISTER + pin;
+                let events = bits >> (4 * pin);
+                if events & (1 << EDGE_LOW_BIT) != 0 {
+                    edges.switches[gpio].record(SwitchState::Pressed, ticks);
+                    if gpio == FLOW_METER_GPIO {
+                        edges.flow_meter_pulses = edges.flow_meter_pulses.wrapping_add(1);
+                    }
+                }
+                if events & (1 << EDGE_HIGH_BIT) != 0 {
+                    edges.switches[gpio].record(SwitchState::Released, ticks);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,92 @@
+use core::sync::atomic::compiler_fence;
+use core::sync::atomic::Ordering;
+
+use bsp::hal::rom_data;
+use dwight::hardware_interface::StorageSlot;
+use dwight::hardware_interface::STORAGE_SLOT_SIZE;
+use rp_pico as bsp;
+
+const FLASH_SIZE: u32 = 2 * 1024 * 1024;
+const XIP_BASE: u32 = 0x1000_0000;
+/// The second stage bootloader at the start of the flash, which sets up
+/// the fast XIP mode.
+const BOOT2_SIZE: usize = 256;
+const SECTOR_SIZE: usize = 4096;
+/// The storage occupies the last sectors of the flash, which the
+/// linker keeps free of code, see `memory.x`.
+const NUM_STORAGE_SECTORS: u32 = 16;
+const STORAGE_START: u32 = FLASH_SIZE - NUM_STORAGE_SECTORS * SECTOR_SIZE as u32;
+const BLOCK_SIZE: u32 = 65536;
+const BLOCK_ERASE_CMD: u8 = 0xD8;
+/// Marks a sector which was erased but never written.
+const ERASED_LENGTH: u32 = u32::MAX;
+
+/// Every slot occupies one sector which starts with the length of the
+/// data as a little endian u32.
+fn sector_offset(slot: StorageSlot) -> u32 {
+    let index = slot.index() as u32;
+    assert!(index < NUM_STORAGE_SECTORS);
+    STORAGE_START + index * SECTOR_SIZE as u32
+}
+
+pub fn read(slot: StorageSlot) -> &'static [u8] {
+    let address = (XIP_BASE + sector_offset(slot)) as *const u8;
+    // Safety: the flash is memory mapped and only written by `write`,
+    // which flushes the cache afterwards.
+    let sector = unsafe { core::slice::from_raw_parts(address, SECTOR_SIZE) };
+    let length = u32::from_le_bytes(sector[..4].try_into().unwrap());
+    if length == ERASED_LENGTH || length as usize > STORAGE_SLOT_SIZE {
+        &[]
+    } else {
+        &sector[4..4 + length as usize]
+    }
+}
+
+pub fn write(slot: StorageSlot, data: &[u8]) {
+    assert!(data.len() <= STORAGE_SLOT_SIZE);
+    let mut sector = [0xFF; SECTOR_SIZE];
+    sector[..4].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    sector[4..4 + data.len()].copy_from_slice(data);
+    // The ROM only restores a slow XIP mode, so the boot2 copy in RAM is
+    // called afterwards to set up the fast one again.
+    let mut boot2 = [0u32; BOOT2_SIZE / 4];
+    // Safety: the boot2 is memory mapped at the start of the flash.
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            XIP_BASE as *const u8,
+            boot2.as_mut_ptr() as *mut u8,
+            BOOT2_SIZE,
+        );
+    }
+    // Safety: interrupts are disabled and no code runs from flash
+    // while it is being written.
+    cortex_m::interrupt::free(|_| unsafe {
+        erase_and_program(sector_offset(slot), &sector, &boot2);
+    });
+}
+
+/// Runs from RAM, since the flash cannot be read while it is written.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn erase_and_program(
+    offset: u32,
+    sector: &[u8; SECTOR_SIZE],
+    boot2: &[u32; BOOT2_SIZE / 4],
+) {
+    // Look up all ROM functions before leaving the XIP mode.
+    let connect_internal_flash = rom_data::connect_internal_flash::ptr();
+    let flash_exit_xip = rom_data::flash_exit_xip::ptr();
+    let flash_range_erase = rom_data::flash_range_erase::ptr();
+    let flash_range_program = rom_data::flash_range_program::ptr();
+    let flash_flush_cache = rom_data::flash_flush_cache::ptr();
+    // The boot2 is Thumb code, hence the set lowest bit.
+    let enter_fast_xip: extern "C" fn() = core::mem::transmute(boot2.as_ptr() as usize + 1);
+    compiler_fence(Ordering::SeqCst);
+    connect_internal_flash();
+    flash_exit_xip();
+    flash_range_erase(offset, SECTOR_SIZE, BLOCK_SIZE, BLOCK_ERASE_CMD);
+    flash_range_program(offset, sector.as_ptr(), SECTOR_SIZE);
+    flash_flush_cache();
+    enter_fast_xip();
+    compiler_fence(Ordering::SeqCst);
+}
@@ -2,17 +2,34 @@
 #![no_main]
 
 mod dwight_pins;
+mod edges;
+mod flash;
+#[cfg(feature = "interrupt-runtime")]
+mod sleep;
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
 
 use bsp::entry;
 use bsp::hal::clocks::init_clocks_and_plls;
 use bsp::hal::clocks::Clock;
+use bsp::hal::fugit::RateExtU32;
 use bsp::hal::pac;
 use bsp::hal::pwm::FreeRunning;
 use bsp::hal::pwm::Pwm0;
 use bsp::hal::pwm::Pwm7;
 use bsp::hal::pwm::Slice;
+use bsp::hal::rosc::Enabled;
+use bsp::hal::rosc::RingOscillator;
 use bsp::hal::sio::Sio;
 use bsp::hal::timer::Instant;
+use bsp::hal::uart::DataBits;
+use bsp::hal::uart::Enabled as UartEnabled;
+use bsp::hal::uart::StopBits;
+use bsp::hal::uart::UartConfig;
+use bsp::hal::uart::UartPeripheral;
 use bsp::hal::watchdog::Watchdog;
 use bsp::hal::Timer;
 use bsp::hal::{self};
@@ -23,18 +40,23 @@ use dwight::hardware_interface::HardwareInterface;
 use dwight::hardware_interface::Led;
 use dwight::hardware_interface::LedState;
 use dwight::hardware_interface::RelayState;
+use dwight::hardware_interface::StorageSlot;
 use dwight::hardware_interface::Switch;
 use dwight::hardware_interface::SwitchState;
 use dwight::main_loop;
 use dwight::Duration;
 use dwight::Time;
+use dwight::TimeUs;
 use dwight_pins::DwightPins;
+use dwight_pins::UartPins;
 use embedded_alloc::Heap;
 use embedded_hal::digital::v2::InputPin;
 use embedded_hal::digital::v2::OutputPin;
 use embedded_hal::PwmPin;
 use panic_probe as _;
 use rp_pico as bsp;
+#[cfg(feature = "interrupt-runtime")]
+use sleep::Sleeper;
 
 #[global_allocator]
 static HEAP: Heap = Heap::empty();
@@ -45,6 +67,13 @@ pub const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
 const AUDIO_PWM_DIVIDER: u8 = 80;
 
+const UART_BAUD_RATE: u32 = 115_200;
+const MAX_SERIAL_LINE_LENGTH: usize = 256;
+
+/// For the common YF-S401 hall effect sensor.
+#[cfg(feature = "flow-meter")]
+const FLOW_METER_PULSES_PER_ML: f32 = 5.88;
+
 /// Value from the Raspberry Pi Pico hal pwm_blink template.
 pub const DEFAULT_LED_TOP: f32 = 25000.0;
 
@@ -55,12 +84,18 @@ pub struct Dwight {
     delay: Delay,
     timer: Timer,
     start: Instant,
+    rosc: RingOscillator<Enabled>,
+    uart: UartPeripheral<UartEnabled, pac::UART0, UartPins>,
+    serial_line: Vec<u8>,
+    #[cfg(feature = "interrupt-runtime")]
+    sleeper: Sleeper,
 }
 
 impl Dwight {
     fn new() -> Self {
         let mut pac = pac::Peripherals::take().unwrap();
-        let core = pac::CorePeripherals::take().unwrap();
+        #[allow(unused_mut)]
+        let mut core = pac::CorePeripherals::take().unwrap();
         let mut watchdog = Watchdog::new(pac.WATCHDOG);
         let sio = Sio::new(pac.SIO);
 
@@ -77,7 +112,8 @@ impl Dwight {
         .unwrap();
 
         let delay = Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
-        let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+        #[allow(unused_mut)]
+        let mut timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
 
         let pins = hal::gpio::Pins::new(
             pac.IO_BANK0,
@@ -102,6 +138,27 @@ impl Dwight {
         led_pwm.channel_b.output_to(right_led);
         led_pwm.set_top(DEFAULT_LED_TOP as u16);
 
+        let rosc = RingOscillator::new(pac.ROSC).initialize();
+
+        #[allow(unused_mut)]
+        let mut uart = UartPeripheral::new(pac.UART0, pins.uart_pins(), &mut pac.RESETS)
+            .enable(
+                UartConfig::new(UART_BAUD_RATE.Hz(), DataBits::Eight, None, StopBits::One),
+                clocks.peripheral_clock.freq(),
+            )
+            .unwrap();
+
+        #[cfg(feature = "interrupt-runtime")]
+        let sleeper = {
+            uart.enable_rx_interrupt();
+            Sleeper::new(&mut timer, &mut core.SCB)
+        };
+
+        edges::start_capturing(timer, pins.switch_pins());
+        #[cfg(feature = "flow-meter")]
+        pins.flow_meter
+            .set_interrupt_enabled(hal::gpio::Interrupt::EdgeLow, true);
+
         let start = timer.get_counter();
         Dwight {
             pins,
@@ -110,27 +167,18 @@ impl Dwight {
             delay,
             timer,
             start,
+            rosc,
+            uart,
+            serial_line: Vec::new(),
+            #[cfg(feature = "interrupt-runtime")]
+            sleeper,
         }
     }
 }
 
 impl HardwareInterface for Dwight {
     fn get_switch_state(&mut self, switch: Switch) -> SwitchState {
-        let is_pressed = match switch {
-            Switch::Number0 => self.pins.number_switches[0].is_low(),
-            Switch::Number1 => self.pins.number_switches[1].is_low(),
-            Switch::Number2 => self.pins.number_switches[2].is_low(),
-            Switch::Number3 => self.pins.number_switches[3].is_low(),
-            Switch::Number4 => self.pins.number_switches[4].is_low(),
-            Switch::Number5 => self.pins.number_switches[5].is_low(),
-            Switch::Number6 => self.pins.number_switches[6].is_low(),
-            Switch::Number7 => self.pins.number_switches[7].is_low(),
-            Switch::Number8 => self.pins.number_switches[8].is_low(),
-            Switch::Number9 => self.pins.number_switches[9].is_low(),
-            Switch::Left => self.pins.left_switch.is_low(),
-            Switch::Right => self.pins.right_switch.is_low(),
-        }
-        .unwrap();
+        let is_pressed = self.pins.switch_pin(switch).is_low().unwrap();
         if is_pressed {
             SwitchState::Pressed
         } else {
@@ -155,10 +203,11 @@ impl HardwareInterface for Dwight {
         };
     }
 
-    fn set_relay_state(&mut self, relay_state: RelayState) {
+    fn set_relay_state(&mut self, pump: usize, relay_state: RelayState) {
+        let relay_pin = &mut self.pins.relay_pins[pump];
         match relay_state {
-            RelayState::On => self.pins.relay_pin.set_high(),
-            RelayState::Off => self.pins.relay_pin.set_low(),
+            RelayState::On => relay_pin.set_high(),
+            RelayState::Off => relay_pin.set_low(),
         }
         .unwrap();
     }
@@ -182,7 +231,75 @@ impl HardwareInterface for Dwight {
             .get_counter()
             .checked_duration_since(self.start)
             .unwrap()
-            .to_millis() as Time
+            .to_millis()
+    }
+
+    fn get_elapsed_time_us(&mut self) -> TimeUs {
+        (self.timer.get_counter() - self.start).to_micros()
+    }
+
+    fn take_switch_edge_us(&mut self, switch: Switch, state: SwitchState) -> Option<TimeUs> {
+        let ticks = edges::take(self.pins.switch_pin(switch), state)?;
+        Some(ticks.saturating_sub(self.start.ticks()))
+    }
+
+    /// Only the first pump has a flow meter.
+    #[cfg(feature = "flow-meter")]
+    fn get_flow_meter_volume_ml(&mut self, pump: usize) -> Option<f32> {
+        (pump == 0).then(|| edges::flow_meter_pulses() as f32 / FLOW_METER_PULSES_PER_ML)
+    }
+
+    #[cfg(feature = "glass-sensor")]
+    fn is_glass_present(&mut self) -> Option<bool> {
+        Some(self.pins.glass_sensor.is_low().unwrap())
+    }
+
+    fn get_random_bits(&mut self) -> u32 {
+        (0..u32::BITS).fold(0, |bits, _| (bits << 1) | self.rosc.get_random_bit() as u32)
+    }
+
+    /// Blocks until the message is in the FIFO, which takes less than
+    /// a ms per ten characters.
+    fn report(&mut self, message: &str) {
+        defmt::info!("{=str}", message);
+        self.uart.write_full_blocking(message.as_bytes());
+        self.uart.write_full_blocking(b"\r\n");
+    }
+
+    fn read_serial_line(&mut self) -> Option<String> {
+        let mut byte = [0];
+        while let Ok(1) = self.uart.read_raw(&mut byte) {
+            match byte[0] {
+                b'\r' => {}
+                b'\n' => {
+                    let line = core::mem::take(&mut self.serial_line);
+                    return String::from_utf8(line).ok();
+                }
+                byte if self.serial_line.len() < MAX_SERIAL_LINE_LENGTH => {
+                    self.serial_line.push(byte)
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    #[cfg(feature = "interrupt-runtime")]
+    fn sleep_until(&mut self, wake_time: Time) {
+        let now = self.get_elapsed_time_ms();
+        if wake_time > now {
+            // The machine never sleeps for more than a few ms.
+            let duration_us = ((wake_time - now) * 1000) as u32;
+            self.sleeper.sleep_for(&self.timer, duration_us);
+        }
+    }
+
+    fn read_storage(&mut self, slot: StorageSlot) -> Vec<u8> {
+        flash::read(slot).into()
+    }
+
+    fn write_storage(&mut self, slot: StorageSlot, data: &[u8]) {
+        flash::write(slot, data);
     }
 }
 
@@ -0,0 +1,92 @@
+use bsp::hal::fugit::ExtU32;
+use bsp::hal::pac::interrupt;
+use bsp::hal::timer::Alarm;
+use bsp::hal::timer::Alarm0;
+use bsp::hal::timer::Instant;
+use bsp::hal::Timer;
+use cortex_m::peripheral::NVIC;
+use cortex_m::peripheral::SCB;
+use rp_pico as bsp;
+
+/// How often the statistics are reported, in µs.
+const REPORT_INTERVAL_US: u64 = 60_000_000;
+
+/// Lets the core sleep until a switch changes, serial data arrives or
+/// the timer alarm fires. Switch edges wake the core through their
+/// interrupt handler. The alarm and the UART need no handlers: with
+/// SEVONPEND, a pending interrupt wakes the core from WFE even while it
+/// is masked in the NVIC, and the pending flags are cleared after
+/// waking up.
+pub struct Sleeper {
+    alarm: Alarm0,
+    stats: SleepStats,
+}
+
+/// Measures how much of the time the core was asleep, which is where
+/// the power is saved, and how late the timer alarms woke it up.
+struct SleepStats {
+    period_start: Instant,
+    asleep_us: u64,
+    num_wake_ups: u32,
+    max_alarm_latency_us: u64,
+}
+
+impl Sleeper {
+    pub fn new(timer: &mut Timer, scb: &mut SCB) -> Self {
+        let mut alarm = timer.alarm_0().unwrap();
+        alarm.enable_interrupt();
+        scb.set_sevonpend();
+        Self {
+            alarm,
+            stats: SleepStats {
+                period_start: timer.get_counter(),
+                asleep_us: 0,
+                num_wake_ups: 0,
+                max_alarm_latency_us: 0,
+            },
+        }
+    }
+
+    pub fn sleep_for(&mut self, timer: &Timer, duration_us: u32) {
+        let start = timer.get_counter();
+        if self.alarm.schedule(duration_us.micros()).is_ok() {
+            // Events since the last wake-up make the core return right
+            // away, so no edge is missed.
+            cortex_m::asm::wfe();
+        }
+        let end = timer.get_counter();
+        let woken_by_alarm = self.alarm.finished();
+        self.alarm.clear_interrupt();
+        NVIC::unpend(interrupt::TIMER_IRQ_0);
+        NVIC::unpend(interrupt::UART0_IRQ);
+        self.stats
+            .record(start, end, woken_by_alarm.then_some(duration_us));
+    }
+}
+
+impl SleepStats {
+    fn record(&mut self, start: Instant, end: Instant, alarm_duration_us: Option<u32>) {
+        let slept_us = (end - start).to_micros();
+        self.asleep_us += slept_us;
+        self.num_wake_ups += 1;
+        if let Some(alarm_duration_us) = alarm_duration_us {
+            let latency_us = slept_us.saturating_sub(alarm_duration_us as u64);
+            self.max_alarm_latency_us = self.max_alarm_latency_us.max(latency_us);
+        }
+        let period_us = (end - self.period_start).to_micros();
+        if period_us >= REPORT_INTERVAL_US {
+            defmt::info!(
+                "Asleep {=u64}% of the time, {=u32} wake-ups, max alarm latency {=u64} us",
+                self.asleep_us * 100 / period_us,
+                self.num_wake_ups,
+                self.max_alarm_latency_us
+            );
+            *self = SleepStats {
+                period_start: end,
+                asleep_us: 0,
+                num_wake_ups: 0,
+                max_alarm_latency_us: 0,
+            };
+        }
+    }
+}
@@ -1,8 +1,12 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
 use enum_map::Enum;
 use enum_map::EnumMap;
 
 use crate::Duration;
 use crate::Time;
+use crate::TimeUs;
 
 #[derive(Debug)]
 pub struct LedState {
@@ -25,6 +29,10 @@ pub enum Led {
     Right,
 }
 
+/// The number of pumps, each switched by its own relay and usually
+/// drawing from its own bottle. Pumps are numbered from zero.
+pub const NUM_PUMPS: usize = 4;
+
 #[derive(Debug)]
 pub enum RelayState {
     On,
@@ -54,6 +62,33 @@ impl Frequency {
     pub const C5: Frequency = Frequency::Some(523.26);
 }
 
+/// The maximum number of bytes stored in a single storage slot.
+pub const STORAGE_SLOT_SIZE: usize = 4092;
+
+pub const NUM_SCRIPT_SLOTS: usize = 3;
+
+/// The parts of the persistent storage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageSlot {
+    Script(usize),
+    /// The state of the running program.
+    Snapshot,
+    Recipes,
+    Statistics,
+}
+
+impl StorageSlot {
+    /// A unique number for every slot, starting from zero.
+    pub fn index(&self) -> usize {
+        match self {
+            StorageSlot::Script(num) => *num,
+            StorageSlot::Snapshot => NUM_SCRIPT_SLOTS,
+            StorageSlot::Recipes => NUM_SCRIPT_SLOTS + 1,
+            StorageSlot::Statistics => NUM_SCRIPT_SLOTS + 2,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Enum, Clone, Copy)]
 pub enum Switch {
     // Enumerate these explicitly here instead of doing
@@ -109,7 +144,7 @@ impl Switch {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SwitchState {
     Pressed,
     Released,
@@ -124,9 +159,53 @@ impl SwitchState {
     }
 }
 
+/// The first edge of a switch towards each state since the edges were
+/// last taken, as captured by an interrupt.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SwitchEdges {
+    pressed: Option<TimeUs>,
+    released: Option<TimeUs>,
+}
+
+impl SwitchEdges {
+    pub const fn new() -> Self {
+        Self {
+            pressed: None,
+            released: None,
+        }
+    }
+
+    pub fn record(&mut self, state: SwitchState, time_us: TimeUs) {
+        match state {
+            SwitchState::Pressed => self.pressed.get_or_insert(time_us),
+            SwitchState::Released => self.released.get_or_insert(time_us),
+        };
+    }
+
+    /// The edge towards the state the switch was sampled in. The edge
+    /// towards the other state is dropped as well, it stems from a
+    /// bounce or a tap between two samples and would otherwise stamp
+    /// the next real edge with its old time.
+    pub fn take(&mut self, state: SwitchState) -> Option<TimeUs> {
+        let (pressed, released) = (self.pressed.take(), self.released.take());
+        match state {
+            SwitchState::Pressed => pressed,
+            SwitchState::Released => released,
+        }
+    }
+}
+
 pub struct State {
     current: EnumMap<Switch, SwitchState>,
     previous: EnumMap<Switch, SwitchState>,
+    /// When each switch entered its current state.
+    changed_us: EnumMap<Switch, TimeUs>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl State {
@@ -134,11 +213,27 @@ impl State {
         Self {
             current: EnumMap::from_fn(|_| SwitchState::Released),
             previous: EnumMap::from_fn(|_| SwitchState::Released),
+            changed_us: EnumMap::from_fn(|_| 0),
         }
     }
 
-    fn update(self, new: EnumMap<Switch, SwitchState>) -> Self {
+    /// Switches which changed since the last update get the time of
+    /// their edge if the hardware captured it, or the sampling time
+    /// otherwise.
+    fn update(
+        self,
+        new: EnumMap<Switch, SwitchState>,
+        edges_us: EnumMap<Switch, Option<TimeUs>>,
+        time_us: TimeUs,
+    ) -> Self {
         Self {
+            changed_us: EnumMap::from_fn(|switch| {
+                if new[switch] == self.current[switch] {
+                    self.changed_us[switch]
+                } else {
+                    edges_us[switch].unwrap_or(time_us).min(time_us)
+                }
+            }),
             current: new,
             previous: self.current,
         }
@@ -148,10 +243,27 @@ impl State {
         self.current[switch].is_pressed() && !self.previous[switch].is_pressed()
     }
 
+    pub fn just_released(&self, switch: Switch) -> bool {
+        !self.current[switch].is_pressed() && self.previous[switch].is_pressed()
+    }
+
     pub fn pressed(&self, switch: Switch) -> bool {
         self.current[switch].is_pressed()
     }
 
+    /// The gesture which leaves a program. Only the moment both are
+    /// pressed counts, so that a resumed program is not left again
+    /// right away.
+    pub fn left_and_right_just_pressed(&self) -> bool {
+        let left_and_right = self.pressed(Switch::Left) && self.pressed(Switch::Right);
+        left_and_right && (self.just_pressed(Switch::Left) || self.just_pressed(Switch::Right))
+    }
+
+    /// When the switch was pressed, if it is pressed.
+    pub fn press_time_us(&self, switch: Switch) -> Option<TimeUs> {
+        self.pressed(switch).then_some(self.changed_us[switch])
+    }
+
     pub fn iter_just_pressed(&self) -> impl Iterator<Item = Switch> + '_ {
         self.current
             .iter()
@@ -184,12 +296,88 @@ impl State {
 pub trait HardwareInterface {
     fn get_switch_state(&mut self, switch: Switch) -> SwitchState;
     fn set_led_state(&mut self, led: Led, led_state: LedState);
-    fn set_relay_state(&mut self, relay_state: RelayState);
+    fn set_relay_state(&mut self, pump: usize, relay_state: RelayState);
     fn set_speaker_frequency(&mut self, frequency: &Frequency);
     fn wait_ms(&mut self, delay_ms: Duration);
     fn get_elapsed_time_ms(&mut self) -> Time;
+    fn get_elapsed_time_us(&mut self) -> TimeUs {
+        self.get_elapsed_time_ms() as TimeUs * 1000
+    }
+    /// The time of the first edge of the switch towards the given
+    /// state since the last call, if the hardware captures edges.
+    fn take_switch_edge_us(&mut self, _switch: Switch, _state: SwitchState) -> Option<TimeUs> {
+        None
+    }
+    /// Random bits from a hardware entropy source.
+    fn get_random_bits(&mut self) -> u32;
+    /// Send a human readable message over the serial console.
+    fn report(&mut self, message: &str);
+    /// A line received over the serial console, if there is one.
+    fn read_serial_line(&mut self) -> Option<String>;
+    /// The data last written to the slot of the persistent storage,
+    /// which survives power loss. Empty if nothing was written yet.
+    fn read_storage(&mut self, slot: StorageSlot) -> Vec<u8>;
+    /// Replace the data in the slot. At most `STORAGE_SLOT_SIZE` bytes.
+    fn write_storage(&mut self, slot: StorageSlot, data: &[u8]);
+    /// The total volume in ml counted by the flow meter of the pump
+    /// since boot, if it has one.
+    fn get_flow_meter_volume_ml(&mut self, _pump: usize) -> Option<f32> {
+        None
+    }
+    /// Whether a glass stands under the tap, if there is a sensor.
+    fn is_glass_present(&mut self) -> Option<bool> {
+        None
+    }
+    /// Sleep until the given time or until a switch changes or serial
+    /// data arrives, whichever comes first. Returning early is always
+    /// allowed, so the default simply keeps polling.
+    fn sleep_until(&mut self, _wake_time: Time) {}
 
     fn update_state(&mut self, previous: State) -> State {
-        previous.update(EnumMap::from_fn(|switch| self.get_switch_state(switch)))
+        let new = EnumMap::from_fn(|switch| self.get_switch_state(switch));
+        let time_us = self.get_elapsed_time_us();
+        let edges_us = EnumMap::from_fn(|switch| self.take_switch_edge_us(switch, new[switch]));
+        previous.update(new, edges_us, time_us)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HardwareInterface;
+    use super::State;
+    use super::Switch;
+    use super::SwitchState;
+    use crate::test_utils::MockInterface;
+
+    #[test]
+    fn a_bounce_does_not_stamp_the_release() {
+        let mut interface = MockInterface::default();
+        interface.pressed[Switch::Left] = true;
+        interface.edges_us[Switch::Left].record(SwitchState::Pressed, 1000);
+        interface.edges_us[Switch::Left].record(SwitchState::Released, 1200);
+        interface.time_ms = 2;
+        let state = interface.update_state(State::new());
+        assert_eq!(state.press_time_us(Switch::Left), Some(1000));
+        interface.time_ms = 100;
+        let state = interface.update_state(state);
+        interface.pressed[Switch::Left] = false;
+        interface.time_ms = 200;
+        let state = interface.update_state(state);
+        assert!(state.just_released(Switch::Left));
+        assert_eq!(state.changed_us[Switch::Left], 200_000);
+    }
+
+    #[test]
+    fn a_tap_between_two_samples_does_not_stamp_the_next_press() {
+        let mut interface = MockInterface::default();
+        interface.edges_us[Switch::Left].record(SwitchState::Pressed, 1000);
+        interface.edges_us[Switch::Left].record(SwitchState::Released, 1200);
+        interface.time_ms = 2;
+        let state = interface.update_state(State::new());
+        assert!(!state.pressed(Switch::Left));
+        interface.pressed[Switch::Left] = true;
+        interface.time_ms = 100;
+        let state = interface.update_state(state);
+        assert_eq!(state.press_time_us(Switch::Left), Some(100_000));
     }
 }
@@ -1,20 +1,38 @@
 #![no_std]
-#![feature(const_fn_floating_point_arithmetic)]
 
 extern crate alloc;
 
 use hardware_interface::HardwareInterface;
 use machine::Machine;
 use programs::ProgramSwitching;
+mod calibration;
+mod cocktails;
 mod configuration;
 pub mod hardware_interface;
 mod machine;
+mod maintenance;
 mod melody;
 mod programs;
+mod random;
 mod reaction_tester;
+mod russian_roulette;
+mod script;
+mod simon_says;
+pub mod simulated_pump;
+mod snapshot;
+mod statistics;
+#[cfg(test)]
+mod test_utils;
+mod tournament;
 
-pub type Time = u32;
-pub type Duration = u32;
+/// Milliseconds since boot. With 64 bits, the clock never wraps
+/// around, so times can be compared and subtracted directly.
+pub type Time = u64;
+pub type Duration = u64;
+/// Microseconds since boot, for input events which need more
+/// precision than `Time`.
+pub type TimeUs = u64;
+pub type DurationUs = u64;
 
 pub fn main_loop(interface: impl HardwareInterface) -> ! {
     Machine::new().run(interface, ProgramSwitching::default())
@@ -1,3 +1,5 @@
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
 
 use hardware_interface::Frequency;
@@ -6,25 +8,99 @@ use hardware_interface::Led;
 use hardware_interface::LedState;
 use hardware_interface::RelayState;
 use hardware_interface::State;
+use hardware_interface::StorageSlot;
+use hardware_interface::NUM_PUMPS;
+use hardware_interface::NUM_SCRIPT_SLOTS;
+use hardware_interface::STORAGE_SLOT_SIZE;
 use melody::Melody;
+use melody::BOTTLE_EMPTY;
+use melody::BOTTLE_LOW;
 use melody::CHROMATIC_SCALE;
 use programs::Program;
+use programs::PROGRAMS;
+use rand::Rng;
 
+use crate::cocktails::parse_recipes;
+use crate::cocktails::Recipe;
+use crate::cocktails::RecipeUpload;
 use crate::configuration::Configuration;
 use crate::hardware_interface;
 use crate::melody;
 use crate::melody::Note;
 use crate::programs;
+use crate::random::Random;
+use crate::script::ScriptUpload;
+use crate::statistics::Statistics;
+use crate::statistics::Tally;
 use crate::Duration;
 use crate::Time;
+use crate::TimeUs;
 
 const DELAY_AFTER_SHOT: Time = 300;
 
+/// If the flow meter counts nothing for this long while pouring, the
+/// bottle is empty.
+const EMPTY_BOTTLE_TIMEOUT_MS: Duration = 1000;
+const GLASS_ALERT_FREQUENCY: Frequency = Frequency::A4;
+const GLASS_ALERT_BEEP_MS: Duration = 200;
+
+/// Below this share of its capacity, a bottle is almost empty.
+const LOW_BOTTLE_FRACTION: f32 = 0.15;
+const LOW_BOTTLE_NUM_BLINKS: usize = 5;
+const LOW_BOTTLE_BLINK_DURATION_MS: Duration = 100;
+
+/// A metered pour is stopped after this many times its expected
+/// duration, in case the flow meter fails.
+const METERED_POUR_TIMEOUT_FACTOR: Duration = 3;
+
+/// The main loop wakes up at least this often, since programs check
+/// their deadlines on every update.
+const MAX_SLEEP_DURATION_MS: Duration = 20;
+/// Wake-up interval while an LED transition is ongoing.
+const LED_UPDATE_INTERVAL_MS: Duration = 10;
+/// Wake-up interval during a metered pour, which limits how much the
+/// pour overshoots.
+const FLOW_METER_UPDATE_INTERVAL_MS: Duration = 5;
+
+/// Snapshots are written at most this often, unless the program is switched.
+const SNAPSHOT_INTERVAL_MS: Duration = 30_000;
+/// Changed statistics are written at most this often, to spare the flash.
+const STATISTICS_INTERVAL_MS: Duration = 60_000;
+
+const ATTRACT_MIN_JINGLE_INTERVAL_MS: Duration = 30_000;
+const ATTRACT_MAX_JINGLE_INTERVAL_MS: Duration = 90_000;
+const ATTRACT_SLOW_TRANSITION_DURATION_MS: Duration = 2000;
+const ATTRACT_FAST_TRANSITION_DURATION_MS: Duration = 100;
+const ATTRACT_NUM_FAST_BLINKS: usize = 3;
+
 #[derive(Debug)]
 enum Action {
     SetLedTransition(Led, Transition),
-    SetRelayState(RelayState),
+    SetRelayState(usize, RelayState),
+    /// Turn the relay of the pump on for a pour which the player with
+    /// the button drinks, if known.
+    StartPour(usize, Option<usize>),
+    /// Turn the relay of the pump on until its flow meter counted the
+    /// volume, for the player with the button, if known.
+    PourVolume(usize, f32, Option<usize>),
     SetSpeakerFrequency(Frequency),
+    Report(String),
+}
+
+impl Action {
+    /// The pump whose relay the action switches, if any.
+    fn pump(&self) -> Option<usize> {
+        match self {
+            Action::SetRelayState(pump, _)
+            | Action::StartPour(pump, _)
+            | Action::PourVolume(pump, ..) => Some(*pump),
+            _ => None,
+        }
+    }
+
+    fn is_pouring(&self) -> bool {
+        self.pump().is_some()
+    }
 }
 
 #[derive(Debug)]
@@ -35,16 +111,31 @@ struct TimedAction {
 
 type Queue = Vec<TimedAction>;
 
+/// Data which is sent over the serial console in several lines.
+enum Upload {
+    Script(ScriptUpload),
+    Recipes(RecipeUpload),
+}
+
+/// Shown while nobody has touched Dwight for a while.
+struct AttractMode {
+    next_jingle_ms: Time,
+    /// A key was pressed to leave the attract mode. The program
+    /// continues once all keys have been released, so that the key
+    /// press does not reach it.
+    waking_up: bool,
+}
+
 #[derive(Default, Debug)]
 struct Transition {
     start_val: f32,
     end_val: f32,
-    duration: Time,
+    duration: Duration,
 }
 
 impl Transition {
-    fn get_current_val(&self, time_elapsed: Time) -> LedState {
-        let frac = if time_elapsed <= 0 {
+    fn get_current_val(&self, time_elapsed: Duration) -> LedState {
+        let frac = if time_elapsed == 0 {
             0.0
         } else if time_elapsed >= self.duration {
             1.0
@@ -82,7 +173,7 @@ struct StartedTransition {
 impl StartedTransition {
     fn get_current_val(&self, time_ms: Time) -> LedState {
         self.transition
-            .get_current_val(time_ms - self.start_time_ms)
+            .get_current_val(time_ms.saturating_sub(self.start_time_ms))
     }
 
     fn end_time(&self) -> Time {
@@ -90,13 +181,72 @@ impl StartedTransition {
     }
 }
 
+/// A pour which is stopped by the flow meter.
+struct MeteredPour {
+    target_ml: f32,
+    start_ml: f32,
+    last_ml: f32,
+    /// When the flow meter last counted anything.
+    last_flow_ms: Time,
+    deadline_ms: Time,
+}
+
+/// A relay which is switched on.
+#[derive(Clone, Copy)]
+struct RunningRelay {
+    since_ms: Time,
+    /// The reading of the flow meter when the relay was switched on.
+    meter_ml: Option<f32>,
+    /// The button of the player who drinks what is poured, for the
+    /// statistics.
+    player: Option<usize>,
+}
+
+struct GlassPause {
+    since_ms: Time,
+    paused_relays: [Option<RunningRelay>; NUM_PUMPS],
+}
+
 pub struct Machine {
     actions: Queue,
     time_ms: Time,
+    time_us: TimeUs,
     wait_for_all_actions: bool,
     left_led_transition: StartedTransition,
     right_led_transition: StartedTransition,
     config: Configuration,
+    rng: Random,
+    last_activity_ms: Time,
+    attract_mode: Option<AttractMode>,
+    scripts: [Option<String>; NUM_SCRIPT_SLOTS],
+    recipes: Vec<Recipe>,
+    upload: Option<Upload>,
+    snapshot_requested: bool,
+    next_snapshot_ms: Time,
+    /// The snapshot in the storage, to avoid rewriting the same data.
+    stored_snapshot: Vec<u8>,
+    /// The last reading of the flow meter of each pump, if it has one.
+    flow_meter_ml: [Option<f32>; NUM_PUMPS],
+    metered_pours: [Option<MeteredPour>; NUM_PUMPS],
+    relays: [Option<RunningRelay>; NUM_PUMPS],
+    /// What is left in the bottle of each pump, estimated from the time
+    /// its relay was on. Only meaningful if the capacity is configured.
+    bottle_levels_ml: [f32; NUM_PUMPS],
+    low_bottle_warned: [bool; NUM_PUMPS],
+    /// While the bottle is detached for cleaning, its level stays the same.
+    bottles_detached: [bool; NUM_PUMPS],
+    /// What priming poured into the glass beyond the tube, which the next
+    /// pour of the pump leaves out.
+    prime_credits_ml: [f32; NUM_PUMPS],
+    /// What the glass sensor reports, if there is one.
+    glass_present: Option<bool>,
+    glass_was_present: Option<bool>,
+    glass_pause: Option<GlassPause>,
+    statistics: Statistics,
+    statistics_changed: bool,
+    next_statistics_ms: Time,
+    /// The program which pours, for the statistics.
+    current_program: Option<&'static str>,
 }
 
 impl Machine {
@@ -104,13 +254,47 @@ impl Machine {
         Self {
             actions: Queue::default(),
             time_ms: 0,
+            time_us: 0,
             wait_for_all_actions: false,
             left_led_transition: StartedTransition::default(),
             right_led_transition: StartedTransition::default(),
             config: Configuration::default(),
+            rng: Random::from_seed(0),
+            last_activity_ms: 0,
+            attract_mode: None,
+            scripts: Default::default(),
+            recipes: Vec::new(),
+            upload: None,
+            snapshot_requested: false,
+            next_snapshot_ms: SNAPSHOT_INTERVAL_MS,
+            stored_snapshot: Vec::new(),
+            flow_meter_ml: [None; NUM_PUMPS],
+            metered_pours: Default::default(),
+            relays: [None; NUM_PUMPS],
+            bottle_levels_ml: [0.0; NUM_PUMPS],
+            low_bottle_warned: [false; NUM_PUMPS],
+            bottles_detached: [false; NUM_PUMPS],
+            prime_credits_ml: [0.0; NUM_PUMPS],
+            glass_present: None,
+            glass_was_present: None,
+            glass_pause: None,
+            statistics: Statistics::default(),
+            statistics_changed: false,
+            next_statistics_ms: STATISTICS_INTERVAL_MS,
+            current_program: None,
         }
     }
 
+    pub fn rng(&mut self) -> &mut Random {
+        &mut self.rng
+    }
+
+    fn add_hardware_entropy(&mut self, interface: &mut impl HardwareInterface) {
+        self.rng.add_entropy(interface.get_random_bits() as u64);
+        self.rng.add_entropy(self.time_ms);
+        self.rng.reseed();
+    }
+
     pub fn config(&self) -> &Configuration {
         &self.config
     }
@@ -119,6 +303,12 @@ impl Machine {
         self.time_ms
     }
 
+    /// The same as `time_ms`, for comparing with the times of switch
+    /// edges.
+    pub fn time_us(&self) -> TimeUs {
+        self.time_us
+    }
+
     fn queue_action(&mut self, ms: Duration, action: Action) {
         self.actions.push(TimedAction {
             timing_ms: self.time_ms + ms,
@@ -146,9 +336,290 @@ impl Machine {
                         start_time_ms: action.timing_ms,
                     }
                 }
-                Action::SetRelayState(state) => interface.set_relay_state(state),
+                Action::SetRelayState(pump, state) => self.switch_relay(interface, pump, state),
+                Action::StartPour(pump, player) => self.start_pour(interface, pump, player),
+                Action::PourVolume(pump, volume_ml, player) => {
+                    self.start_metered_pour(interface, pump, volume_ml, player)
+                }
                 Action::SetSpeakerFrequency(freq) => interface.set_speaker_frequency(&freq),
+                Action::Report(message) => interface.report(&message),
+            }
+        }
+    }
+
+    fn switch_relay(
+        &mut self,
+        interface: &mut impl HardwareInterface,
+        pump: usize,
+        state: RelayState,
+    ) {
+        match state {
+            RelayState::On => {
+                self.relays[pump].get_or_insert(RunningRelay {
+                    since_ms: self.time_ms,
+                    meter_ml: self.flow_meter_ml[pump],
+                    player: None,
+                });
+            }
+            RelayState::Off => {
+                let relay = self.relays[pump].take();
+                if let Some(relay) = relay.filter(|_| !self.bottles_detached[pump]) {
+                    self.relay_switched_off(pump, relay);
+                }
+            }
+        }
+        interface.set_relay_state(pump, state);
+    }
+
+    fn start_pour(
+        &mut self,
+        interface: &mut impl HardwareInterface,
+        pump: usize,
+        player: Option<usize>,
+    ) {
+        self.switch_relay(interface, pump, RelayState::On);
+        if let Some(relay) = &mut self.relays[pump] {
+            relay.player = player;
+        }
+    }
+
+    /// What was poured from the bottle counts towards its level and the
+    /// statistics. The flow meter measures it where there is one.
+    fn relay_switched_off(&mut self, pump: usize, relay: RunningRelay) {
+        let pump_config = self.config.pumps[pump];
+        let timed_ml = pump_config.volume_poured_in(self.time_ms - relay.since_ms);
+        self.bottle_levels_ml[pump] -= timed_ml;
+        self.warn_if_bottle_low(pump);
+        let volume_ml = match (relay.meter_ml, self.flow_meter_ml[pump]) {
+            (Some(start_ml), Some(meter_ml)) => meter_ml - start_ml,
+            _ => timed_ml,
+        };
+        self.record_pour(relay.player, pump, volume_ml);
+    }
+
+    fn relays_on(&self) -> [bool; NUM_PUMPS] {
+        self.relays.map(|relay| relay.is_some())
+    }
+
+    /// How much the pump poured since its relay was switched on.
+    fn running_volume_ml(&self, pump: usize) -> f32 {
+        self.relays[pump].map_or(0.0, |relay| {
+            self.config.pumps[pump].volume_poured_in(self.time_ms - relay.since_ms)
+        })
+    }
+
+    /// The estimated volume left in the bottle of the pump, if its
+    /// capacity is configured.
+    pub fn bottle_level_ml(&self, pump: usize) -> Option<f32> {
+        self.config.pumps[pump].bottle_capacity_ml?;
+        Some((self.bottle_levels_ml[pump] - self.running_volume_ml(pump)).max(0.0))
+    }
+
+    /// A full bottle with the configured capacity was put in place.
+    pub fn new_bottle(&mut self, pump: usize) {
+        // What the running pump poured so far came from the old bottle.
+        self.bottle_levels_ml[pump] = self.config.pumps[pump].bottle_capacity_ml.unwrap_or(0.0)
+            + self.running_volume_ml(pump);
+        self.low_bottle_warned[pump] = false;
+    }
+
+    /// The pump pours water or air until the bottle is attached again.
+    /// This empties the tube, so nothing is left over from priming.
+    pub fn detach_bottle(&mut self, pump: usize) {
+        self.bottles_detached[pump] = true;
+        self.prime_credits_ml[pump] = 0.0;
+    }
+
+    pub fn attach_bottle(&mut self, pump: usize) {
+        self.bottles_detached[pump] = false;
+    }
+
+    /// The tube of the pump was filled by pouring the volume. Whatever
+    /// did not fit into the tube ended up in the glass.
+    pub fn primed(&mut self, pump: usize, volume_ml: f32) {
+        self.prime_credits_ml[pump] = (volume_ml - self.config.pumps[pump].tube_volume_ml).max(0.0);
+    }
+
+    /// Warns once per bottle, with the melody after the ongoing pour.
+    fn warn_if_bottle_low(&mut self, pump: usize) {
+        let (Some(capacity_ml), Some(level_ml)) = (
+            self.config.pumps[pump].bottle_capacity_ml,
+            self.bottle_level_ml(pump),
+        ) else {
+            return;
+        };
+        if self.low_bottle_warned[pump] || level_ml >= capacity_ml * LOW_BOTTLE_FRACTION {
+            return;
+        }
+        self.low_bottle_warned[pump] = true;
+        self.report(format!(
+            "Bottle of pump {} almost empty, about {:.0} ml left",
+            pump + 1,
+            level_ml
+        ));
+        self.play_melody_after_queued_actions(BOTTLE_LOW);
+        for led in [Led::Left, Led::Right] {
+            self.blink_led(
+                led,
+                LOW_BOTTLE_NUM_BLINKS,
+                LOW_BOTTLE_BLINK_DURATION_MS,
+                LOW_BOTTLE_BLINK_DURATION_MS,
+            );
+        }
+    }
+
+    /// Whether the glass sensor reports a missing glass while something
+    /// is poured or about to be.
+    fn glass_missing_for_pour(&self) -> bool {
+        self.glass_present == Some(false)
+            && (self.relays_on().contains(&true)
+                || self.actions.iter().any(|action| {
+                    action.timing_ms <= self.time_ms
+                        && matches!(
+                            action.action,
+                            Action::SetRelayState(_, RelayState::On)
+                                | Action::StartPour(..)
+                                | Action::PourVolume(..)
+                        )
+                }))
+    }
+
+    /// While the glass is missing, the queued actions are held back and
+    /// all relays are off. Everything continues where it stopped once the
+    /// glass is back.
+    fn update_glass_pause(&mut self, interface: &mut impl HardwareInterface) {
+        match &self.glass_pause {
+            None if self.glass_missing_for_pour() => {
+                self.glass_pause = Some(GlassPause {
+                    since_ms: self.time_ms,
+                    paused_relays: self.relays,
+                });
+                for pump in 0..NUM_PUMPS {
+                    self.switch_relay(interface, pump, RelayState::Off);
+                }
+            }
+            Some(pause) if self.glass_present != Some(false) => {
+                let paused_relays = pause.paused_relays;
+                self.end_glass_pause();
+                interface.set_speaker_frequency(&Frequency::Silence);
+                for (pump, relay) in paused_relays.into_iter().enumerate() {
+                    if let Some(relay) = relay {
+                        self.start_pour(interface, pump, relay.player);
+                    }
+                }
+            }
+            Some(pause) => {
+                let beeping = (self.time_ms - pause.since_ms) % (2 * GLASS_ALERT_BEEP_MS)
+                    < GLASS_ALERT_BEEP_MS;
+                interface.set_speaker_frequency(if beeping {
+                    &GLASS_ALERT_FREQUENCY
+                } else {
+                    &Frequency::Silence
+                });
             }
+            None => {}
+        }
+    }
+
+    /// Drop the pour which waits for the glass, so that a missing or
+    /// broken glass sensor cannot lock Dwight.
+    fn cancel_glass_pause(&mut self) {
+        self.stop_pouring();
+        self.cancel_sounds_and_lights();
+        self.wait_for_all_actions = false;
+        self.report("Pour cancelled".into());
+    }
+
+    /// Delay everything that was queued by the duration of the pause.
+    fn end_glass_pause(&mut self) {
+        let Some(pause) = self.glass_pause.take() else {
+            return;
+        };
+        let paused_ms = self.time_ms - pause.since_ms;
+        for action in self.actions.iter_mut() {
+            action.timing_ms += paused_ms;
+        }
+        for pour in self.metered_pours.iter_mut().flatten() {
+            pour.deadline_ms += paused_ms;
+            pour.last_flow_ms = self.time_ms;
+        }
+    }
+
+    /// Players confirm that their glass is in place by pressing any
+    /// button, or simply by putting it under Dwight if there is a glass
+    /// sensor. Pours wait for the glass either way.
+    pub fn glass_confirmed(&self, state: &State) -> bool {
+        let glass_just_placed =
+            self.glass_present == Some(true) && self.glass_was_present == Some(false);
+        state.anything_just_pressed() || glass_just_placed
+    }
+
+    /// Pours queued while another one of the same pump is running
+    /// extend it.
+    fn start_metered_pour(
+        &mut self,
+        interface: &mut impl HardwareInterface,
+        pump: usize,
+        volume_ml: f32,
+        player: Option<usize>,
+    ) {
+        let timeout =
+            self.config.pumps[pump].pour_duration(volume_ml) * METERED_POUR_TIMEOUT_FACTOR;
+        if let Some(pour) = &mut self.metered_pours[pump] {
+            pour.target_ml += volume_ml;
+            pour.deadline_ms += timeout;
+            return;
+        }
+        let meter_ml = self.flow_meter_ml[pump].unwrap_or(0.0);
+        self.metered_pours[pump] = Some(MeteredPour {
+            target_ml: volume_ml,
+            start_ml: meter_ml,
+            last_ml: meter_ml,
+            last_flow_ms: self.time_ms,
+            deadline_ms: self.time_ms + timeout,
+        });
+        self.start_pour(interface, pump, player);
+    }
+
+    fn metered_pour_running(&self) -> bool {
+        self.metered_pours.iter().any(Option::is_some)
+    }
+
+    fn update_metered_pour(&mut self, interface: &mut impl HardwareInterface, pump: usize) {
+        let Some(pour) = &mut self.metered_pours[pump] else {
+            return;
+        };
+        let meter_ml = self.flow_meter_ml[pump].unwrap_or(pour.last_ml);
+        if meter_ml > pour.last_ml {
+            pour.last_ml = meter_ml;
+            pour.last_flow_ms = self.time_ms;
+        }
+        let dispensed_ml = meter_ml - pour.start_ml;
+        let bottle_empty =
+            self.time_ms.saturating_sub(pour.last_flow_ms) >= EMPTY_BOTTLE_TIMEOUT_MS;
+        let target_ml = pour.target_ml;
+        if dispensed_ml < target_ml && self.time_ms < pour.deadline_ms && !bottle_empty {
+            return;
+        }
+        self.metered_pours[pump] = None;
+        self.switch_relay(interface, pump, RelayState::Off);
+        if bottle_empty {
+            interface.report(&format!(
+                "Bottle of pump {} empty, poured {:.0} of {:.0} ml",
+                pump + 1,
+                dispensed_ml,
+                target_ml
+            ));
+            // The other pumps may still have something left.
+            self.actions
+                .retain(|action| action.action.pump() != Some(pump));
+            self.play_melody(BOTTLE_EMPTY);
+        } else {
+            interface.report(&format!(
+                "Poured {:.0} ml from pump {}",
+                dispensed_ml,
+                pump + 1
+            ));
         }
     }
 
@@ -165,56 +636,566 @@ impl Machine {
 
     pub fn run(mut self, mut interface: impl HardwareInterface, mut program: impl Program) -> ! {
         let mut state = State::new();
+        self.boot(&mut interface, &mut program);
         loop {
-            self.time_ms = interface.get_elapsed_time_ms();
-            state = interface.update_state(state);
-            if self.wait_for_all_actions {
-                self.wait_for_all_actions = !self.actions.is_empty();
-            } else {
-                program.update(&mut self, &state);
+            state = self.step(&mut interface, &mut program, state);
+        }
+    }
+
+    pub(crate) fn boot(
+        &mut self,
+        interface: &mut impl HardwareInterface,
+        program: &mut impl Program,
+    ) {
+        self.time_ms = interface.get_elapsed_time_ms();
+        self.last_activity_ms = self.time_ms;
+        self.add_hardware_entropy(interface);
+        self.load_scripts(interface);
+        self.load_recipes(interface);
+        self.load_statistics(interface);
+        self.stored_snapshot = interface.read_storage(StorageSlot::Snapshot);
+        if !self.stored_snapshot.is_empty() {
+            program.restore(self, &self.stored_snapshot.clone());
+        }
+    }
+
+    /// A single iteration of the main loop.
+    pub(crate) fn step(
+        &mut self,
+        interface: &mut impl HardwareInterface,
+        program: &mut impl Program,
+        state: State,
+    ) -> State {
+        self.time_ms = interface.get_elapsed_time_ms();
+        self.time_us = interface.get_elapsed_time_us();
+        self.flow_meter_ml = core::array::from_fn(|pump| interface.get_flow_meter_volume_ml(pump));
+        self.glass_was_present = self.glass_present;
+        self.glass_present = interface.is_glass_present();
+        while let Some(line) = interface.read_serial_line() {
+            self.receive_serial_line(interface, line);
+        }
+        let state = interface.update_state(state);
+        // The exact timing of button presses is a good source of entropy.
+        if state.anything_just_pressed() {
+            self.add_hardware_entropy(interface);
+        }
+        if state.anything_pressed() {
+            self.last_activity_ms = self.time_ms;
+        }
+        if self.attract_mode.is_some() {
+            self.update_attract_mode(program, &state);
+        } else if self.glass_pause.is_some() {
+            // Nothing moves on until the glass is back, unless the pour
+            // is given up with the gesture which leaves the program.
+            if state.left_and_right_just_pressed() {
+                self.cancel_glass_pause();
+                program.update(self, &state);
+            }
+        } else if self.wait_for_all_actions {
+            self.wait_for_all_actions = !self.actions.is_empty() || self.metered_pour_running();
+        } else if self.idle_for_too_long() {
+            self.enter_attract_mode(program);
+        } else {
+            program.update(self, &state);
+            self.save_snapshot_if_due(interface, program);
+        }
+        self.save_statistics_if_due(interface);
+        self.update_glass_pause(interface);
+        if self.glass_pause.is_none() {
+            self.perform_pending_actions(interface);
+            for pump in 0..NUM_PUMPS {
+                self.update_metered_pour(interface, pump);
+            }
+        }
+        self.update_leds(interface);
+        interface.sleep_until(self.next_wake_time());
+        state
+    }
+
+    /// The time at which the main loop has something to do next,
+    /// unless a switch changes before.
+    fn next_wake_time(&self) -> Time {
+        let mut wake_time = self.time_ms + MAX_SLEEP_DURATION_MS;
+        if !self.no_ongoing_led_transition() {
+            wake_time = wake_time.min(self.time_ms + LED_UPDATE_INTERVAL_MS);
+        }
+        if self.metered_pour_running() {
+            wake_time = wake_time.min(self.time_ms + FLOW_METER_UPDATE_INTERVAL_MS);
+        }
+        if self.glass_pause.is_some() {
+            // The queued actions are held back anyway.
+            return self.time_ms + LED_UPDATE_INTERVAL_MS;
+        }
+        self.actions
+            .iter()
+            .map(|action| action.timing_ms)
+            .fold(wake_time, Time::min)
+    }
+
+    fn idle_for_too_long(&self) -> bool {
+        self.config.idle_timeout != 0
+            && self.actions.is_empty()
+            && !self.metered_pour_running()
+            && self.time_ms.saturating_sub(self.last_activity_ms) >= self.config.idle_timeout
+    }
+
+    fn enter_attract_mode(&mut self, program: &mut impl Program) {
+        program.cleanup_before_switch(self);
+        self.stop_pouring();
+        self.cancel_sounds_and_lights();
+        self.report("Entering attract mode".into());
+        self.attract_mode = Some(AttractMode {
+            next_jingle_ms: self.time_ms + ATTRACT_MIN_JINGLE_INTERVAL_MS,
+            waking_up: false,
+        });
+    }
+
+    fn update_attract_mode(&mut self, program: &mut impl Program, state: &State) {
+        let Some(attract_mode) = self.attract_mode.as_mut() else {
+            return;
+        };
+        if attract_mode.waking_up {
+            if !state.anything_pressed() {
+                self.attract_mode = None;
+                program.on_resume(self);
+            }
+            return;
+        }
+        if state.anything_just_pressed() {
+            attract_mode.waking_up = true;
+            self.cancel_sounds_and_lights();
+            return;
+        }
+        if self.time_ms >= attract_mode.next_jingle_ms {
+            attract_mode.next_jingle_ms = self.time_ms
+                + self
+                    .rng
+                    .gen_range(ATTRACT_MIN_JINGLE_INTERVAL_MS..=ATTRACT_MAX_JINGLE_INTERVAL_MS);
+            let index = self.rng.gen_range(0..PROGRAMS.len());
+            self.play_melody(PROGRAMS[index].melody);
+        }
+        if self.no_ongoing_led_transition() && !self.led_transition_queued() {
+            self.play_ambient_led_animation();
+        }
+    }
+
+    fn play_ambient_led_animation(&mut self) {
+        match self.rng.gen_range(0..3) {
+            0 => {
+                // Both LEDs breathe slowly.
+                for led in [Led::Left, Led::Right] {
+                    self.queue_led_flash(
+                        led,
+                        0,
+                        ATTRACT_SLOW_TRANSITION_DURATION_MS,
+                        ATTRACT_SLOW_TRANSITION_DURATION_MS,
+                    );
+                }
+            }
+            1 => {
+                // The light wanders from one side to the other.
+                self.queue_led_flash(Led::Left, 0, ATTRACT_SLOW_TRANSITION_DURATION_MS, 0);
+                self.queue_led_flash(
+                    Led::Right,
+                    ATTRACT_SLOW_TRANSITION_DURATION_MS,
+                    ATTRACT_SLOW_TRANSITION_DURATION_MS,
+                    0,
+                );
+            }
+            _ => {
+                self.blink_led(
+                    Led::Left,
+                    ATTRACT_NUM_FAST_BLINKS,
+                    ATTRACT_FAST_TRANSITION_DURATION_MS,
+                    ATTRACT_FAST_TRANSITION_DURATION_MS,
+                );
+                self.blink_led(
+                    Led::Right,
+                    ATTRACT_NUM_FAST_BLINKS,
+                    ATTRACT_FAST_TRANSITION_DURATION_MS,
+                    ATTRACT_FAST_TRANSITION_DURATION_MS,
+                );
+            }
+        }
+    }
+
+    fn led_transition_queued(&self) -> bool {
+        self.actions
+            .iter()
+            .any(|action| matches!(action.action, Action::SetLedTransition(..)))
+    }
+
+    pub fn request_snapshot(&mut self) {
+        self.snapshot_requested = true;
+    }
+
+    /// Snapshots are never taken while pouring, so that a restored
+    /// program never continues in the middle of a pour.
+    fn save_snapshot_if_due(
+        &mut self,
+        interface: &mut impl HardwareInterface,
+        program: &mut impl Program,
+    ) {
+        let due = self.snapshot_requested || self.time_ms >= self.next_snapshot_ms;
+        if !due || self.wait_for_all_actions || self.relay_action_queued() {
+            return;
+        }
+        self.snapshot_requested = false;
+        self.next_snapshot_ms = self.time_ms + SNAPSHOT_INTERVAL_MS;
+        let snapshot = program.snapshot().unwrap_or_default();
+        if snapshot == self.stored_snapshot {
+            return;
+        }
+        if snapshot.len() > STORAGE_SLOT_SIZE {
+            self.report("Snapshot too large".into());
+            return;
+        }
+        interface.write_storage(StorageSlot::Snapshot, &snapshot);
+        self.stored_snapshot = snapshot;
+    }
+
+    /// A new session starts if there are no valid statistics.
+    fn load_statistics(&mut self, interface: &mut impl HardwareInterface) {
+        let data = interface.read_storage(StorageSlot::Statistics);
+        match Statistics::deserialize(&data, self.time_ms) {
+            Some(statistics) => self.statistics = statistics,
+            None => self.statistics.reset(self.time_ms),
+        }
+        self.next_statistics_ms = self.time_ms + STATISTICS_INTERVAL_MS;
+    }
+
+    fn save_statistics_if_due(&mut self, interface: &mut impl HardwareInterface) {
+        if !self.statistics_changed || self.time_ms < self.next_statistics_ms {
+            return;
+        }
+        self.statistics_changed = false;
+        self.next_statistics_ms = self.time_ms + STATISTICS_INTERVAL_MS;
+        interface.write_storage(
+            StorageSlot::Statistics,
+            &self.statistics.serialize(self.time_ms),
+        );
+    }
+
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    pub fn reset_statistics(&mut self) {
+        self.statistics.reset(self.time_ms);
+        self.statistics_changed = true;
+    }
+
+    pub fn set_current_program(&mut self, id: &'static str) {
+        self.current_program = Some(id);
+    }
+
+    /// Shots are counted in the shot volume of the pump.
+    fn record_pour(&mut self, player: Option<usize>, pump: usize, volume_ml: f32) {
+        if volume_ml <= 0.0 {
+            return;
+        }
+        let tally = Tally {
+            num_shots: volume_ml / self.config.pumps[pump].shot_volume_ml,
+            volume_ml,
+        };
+        self.statistics
+            .record(self.time_ms, self.current_program, player, tally);
+        self.statistics_changed = true;
+    }
+
+    fn relay_action_queued(&self) -> bool {
+        self.metered_pour_running() || self.actions.iter().any(|action| action.action.is_pouring())
+    }
+
+    fn load_scripts(&mut self, interface: &mut impl HardwareInterface) {
+        for (slot, script) in self.scripts.iter_mut().enumerate() {
+            let data = interface.read_storage(StorageSlot::Script(slot));
+            *script = String::from_utf8(data)
+                .ok()
+                .filter(|source| !source.is_empty());
+        }
+    }
+
+    /// Invalid recipes in the storage are ignored.
+    fn load_recipes(&mut self, interface: &mut impl HardwareInterface) {
+        let data = interface.read_storage(StorageSlot::Recipes);
+        self.recipes = String::from_utf8(data)
+            .ok()
+            .and_then(|source| parse_recipes(&source).ok())
+            .unwrap_or_default();
+    }
+
+    fn receive_serial_line(&mut self, interface: &mut impl HardwareInterface, line: String) {
+        let complete = match self.upload.as_mut() {
+            None => {
+                self.start_upload(&line);
+                return;
+            }
+            Some(Upload::Script(upload)) => upload.add_line(line),
+            Some(Upload::Recipes(upload)) => upload.add_line(line),
+        };
+        if !complete {
+            return;
+        }
+        match self.upload.take().unwrap() {
+            Upload::Script(upload) => self.finish_script_upload(interface, upload),
+            Upload::Recipes(upload) => self.finish_recipe_upload(interface, upload),
+        }
+    }
+
+    fn start_upload(&mut self, line: &str) {
+        if let Some(upload) = ScriptUpload::start(line, NUM_SCRIPT_SLOTS) {
+            self.report(format!("Receiving script {}", upload.slot + 1));
+            self.upload = Some(Upload::Script(upload));
+        } else if let Some(upload) = RecipeUpload::start(line) {
+            self.report("Receiving recipes".into());
+            self.upload = Some(Upload::Recipes(upload));
+        } else {
+            self.report(format!("Unknown command: {}", line));
+        }
+    }
+
+    fn finish_script_upload(
+        &mut self,
+        interface: &mut impl HardwareInterface,
+        upload: ScriptUpload,
+    ) {
+        let slot = upload.slot;
+        match upload.finish() {
+            Ok(source) => {
+                interface.write_storage(StorageSlot::Script(slot), source.as_bytes());
+                self.set_script(slot, source);
+                self.report(format!("Stored script {}", slot + 1));
+            }
+            Err(message) => self.report(message),
+        }
+    }
+
+    fn finish_recipe_upload(
+        &mut self,
+        interface: &mut impl HardwareInterface,
+        upload: RecipeUpload,
+    ) {
+        match upload.finish() {
+            Ok((source, recipes)) => {
+                interface.write_storage(StorageSlot::Recipes, source.as_bytes());
+                self.report(format!("Stored {} recipes", recipes.len()));
+                self.set_recipes(recipes);
             }
-            self.perform_pending_actions(&mut interface);
-            self.update_leds(&mut interface);
+            Err(message) => self.report(message),
+        }
+    }
+
+    pub fn script(&self, slot: usize) -> Option<&str> {
+        self.scripts[slot].as_deref()
+    }
+
+    pub fn set_script(&mut self, slot: usize, source: String) {
+        self.scripts[slot] = Some(source);
+    }
+
+    pub fn recipes(&self) -> &[Recipe] {
+        &self.recipes
+    }
+
+    pub fn set_recipes(&mut self, recipes: Vec<Recipe>) {
+        self.recipes = recipes;
+    }
+
+    /// Measured by the flow meter of the pump if it has one, otherwise
+    /// timed according to its calibrated flow rate. What priming already
+    /// poured into the glass is left out.
+    pub fn pour_volume(&mut self, pump: usize, offset: Duration, volume_ml: f32) {
+        self.pour_volume_for(None, pump, offset, volume_ml);
+    }
+
+    /// Like `pour_volume`, counted for the player with the button in
+    /// the statistics, if known.
+    fn pour_volume_for(
+        &mut self,
+        player: Option<usize>,
+        pump: usize,
+        offset: Duration,
+        volume_ml: f32,
+    ) {
+        let credit_ml = self.prime_credits_ml[pump].min(volume_ml);
+        self.prime_credits_ml[pump] -= credit_ml;
+        let volume_ml = volume_ml - credit_ml;
+        if volume_ml <= 0.0 {
+            return;
+        }
+        if self.flow_meter_ml[pump].is_some() {
+            self.queue_action(offset, Action::PourVolume(pump, volume_ml, player));
+        } else {
+            let duration = self.config.pumps[pump].pour_duration(volume_ml);
+            self.pour_for_player(player, pump, offset, duration);
         }
     }
 
-    pub fn pour(&mut self, offset: Duration) {
-        self.queue_action(offset, Action::SetRelayState(RelayState::On));
+    /// Switch the relay of the pump on for the duration, regardless of
+    /// the volume.
+    pub fn pour_for(&mut self, pump: usize, offset: Duration, duration: Duration) {
+        self.pour_for_player(None, pump, offset, duration);
+    }
+
+    fn pour_for_player(
+        &mut self,
+        player: Option<usize>,
+        pump: usize,
+        offset: Duration,
+        duration: Duration,
+    ) {
+        self.queue_action(offset, Action::StartPour(pump, player));
         self.queue_action(
-            offset + self.config.shot_duration,
-            Action::SetRelayState(RelayState::Off),
+            offset + duration,
+            Action::SetRelayState(pump, RelayState::Off),
         );
     }
 
+    /// Pour the shots from the pump which is configured for the games.
     pub fn pour_with_melody(&mut self, num: usize) {
-        let duration_per_shot = self.config.shot_duration + DELAY_AFTER_SHOT;
-        for i in 0..num {
-            let note = i.min(CHROMATIC_SCALE.len() - 1);
-            let offset = duration_per_shot * i as u32;
-            self.pour(offset);
+        self.pour_game_shots(None, num);
+    }
+
+    /// Like `pour_with_melody`, counted for the player with the button
+    /// in the statistics.
+    pub fn pour_with_melody_for_player(&mut self, button_num: usize, num: usize) {
+        self.pour_game_shots(Some(button_num), num);
+    }
+
+    /// Nothing is poured if the bottle would run dry during the shots,
+    /// since pumping air is no punishment.
+    fn pour_game_shots(&mut self, player: Option<usize>, num: usize) {
+        let pump = self.config.game_pump;
+        let volume_ml = num as f32 * self.config.pumps[pump].shot_volume_ml;
+        if let Some(level_ml) = self
+            .bottle_level_ml(pump)
+            .filter(|level_ml| *level_ml < volume_ml)
+        {
+            self.report(format!(
+                "Not enough left in the bottle of pump {} for {} shots, about {:.0} ml",
+                pump + 1,
+                num,
+                level_ml
+            ));
+            self.play_melody(BOTTLE_EMPTY);
+            return;
+        }
+        self.pour_shots_for(player, pump, num as f32);
+    }
+
+    /// Pour the shots one after another, each with a higher note. A
+    /// fraction of a shot is poured last.
+    pub fn pour_shots_with_melody(&mut self, pump: usize, num: f32) {
+        self.pour_shots_for(None, pump, num);
+    }
+
+    fn pour_shots_for(&mut self, player: Option<usize>, pump: usize, num: f32) {
+        let pump_config = self.config.pumps[pump];
+        let mut offset = 0;
+        let mut remaining = num;
+        let mut note = 0;
+        while remaining > 0.0 {
+            let volume_ml = remaining.min(1.0) * pump_config.shot_volume_ml;
+            self.pour_volume_for(player, pump, offset, volume_ml);
             self.queue_note(&CHROMATIC_SCALE[note], offset);
+            offset += pump_config.pour_duration(volume_ml) + DELAY_AFTER_SHOT;
+            remaining -= 1.0;
+            note = (note + 1).min(CHROMATIC_SCALE.len() - 1);
         }
     }
 
     pub fn flash_led(&mut self, led: Led, transition_duration: Duration, on_duration: Duration) {
+        self.blink_led(led, 1, transition_duration, on_duration);
+    }
+
+    /// Flash the LED the given number of times, one after another.
+    pub fn blink_led(
+        &mut self,
+        led: Led,
+        times: usize,
+        transition_duration: Duration,
+        on_duration: Duration,
+    ) {
+        let period = 2 * transition_duration + 2 * on_duration;
+        for i in 0..times {
+            self.queue_led_flash(
+                led,
+                period * i as Duration,
+                transition_duration,
+                on_duration,
+            );
+        }
+    }
+
+    fn queue_led_flash(
+        &mut self,
+        led: Led,
+        offset: Duration,
+        transition_duration: Duration,
+        on_duration: Duration,
+    ) {
         self.queue_action(
-            0,
+            offset,
             Action::SetLedTransition(led, Transition::on_within(transition_duration)),
         );
         self.queue_action(
-            transition_duration + on_duration,
+            offset + transition_duration + on_duration,
             Action::SetLedTransition(led, Transition::off_within(transition_duration)),
         );
     }
 
+    /// Drop all queued sounds and LED transitions, silence the speaker
+    /// and turn the LEDs off. Relay actions are kept.
+    pub fn cancel_sounds_and_lights(&mut self) {
+        self.actions.retain(|action| {
+            !matches!(
+                action.action,
+                Action::SetSpeakerFrequency(_) | Action::SetLedTransition(..)
+            )
+        });
+        self.set_speaker_frequency(Frequency::Silence);
+        for led in [Led::Left, Led::Right] {
+            self.queue_action(0, Action::SetLedTransition(led, Transition::default()));
+        }
+    }
+
+    /// Drop all queued relay actions and turn all relays off.
+    pub fn stop_pouring(&mut self) {
+        self.end_glass_pause();
+        self.actions.retain(|action| !action.action.is_pouring());
+        self.metered_pours = Default::default();
+        for pump in 0..NUM_PUMPS {
+            self.set_relay_state(pump, RelayState::Off);
+        }
+    }
+
     pub fn play_melody(&mut self, melody: &Melody) {
-        let mut offset = 0;
+        self.queue_melody(melody, 0);
+    }
+
+    /// Play the melody once all currently queued actions have been performed.
+    pub fn play_melody_after_queued_actions(&mut self, melody: &Melody) {
+        let offset = self
+            .actions
+            .iter()
+            .map(|action| action.timing_ms.saturating_sub(self.time_ms))
+            .max()
+            .unwrap_or(0);
+        self.queue_melody(melody, offset);
+    }
+
+    fn queue_melody(&mut self, melody: &Melody, mut offset: Duration) {
         for note in melody.iter() {
             self.queue_note(note, offset);
             offset += note.total_length();
         }
     }
 
+    pub fn play_note(&mut self, note: &Note) {
+        self.queue_note(note, 0);
+    }
+
     fn queue_note(&mut self, note: &Note, offset: Duration) {
         self.queue_action(offset, Action::SetSpeakerFrequency(note.freq.clone()));
         self.queue_action(
@@ -227,14 +1208,18 @@ impl Machine {
         self.wait_for_all_actions = true;
     }
 
-    pub fn set_relay_state(&mut self, state: RelayState) {
-        self.queue_action(0, Action::SetRelayState(state));
+    pub fn set_relay_state(&mut self, pump: usize, state: RelayState) {
+        self.queue_action(0, Action::SetRelayState(pump, state));
     }
 
     pub fn set_speaker_frequency(&mut self, freq: Frequency) {
         self.queue_action(0, Action::SetSpeakerFrequency(freq));
     }
 
+    pub fn report(&mut self, message: String) {
+        self.queue_action(0, Action::Report(message));
+    }
+
     pub fn no_ongoing_led_transition(&self) -> bool {
         self.time_ms > self.left_led_transition.end_time()
             && self.time_ms > self.right_led_transition.end_time()
@@ -250,4 +1235,275 @@ impl Machine {
     pub fn get_config_mut(&mut self) -> &mut Configuration {
         &mut self.config
     }
+
+    #[cfg(test)]
+    pub fn num_queued_actions(&self) -> usize {
+        self.actions.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Machine;
+    use super::StartedTransition;
+    use super::Transition;
+    use crate::hardware_interface::RelayState;
+    use crate::hardware_interface::State;
+    use crate::hardware_interface::Switch;
+    use crate::programs::ContinuousPouring;
+    use crate::programs::Program;
+    use crate::programs::SimplePouring;
+    use crate::simulated_pump::SimulatedPump;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+    use crate::Duration;
+    use crate::Time;
+
+    /// Where a 32 bit millisecond clock wraps around, after 49 days.
+    const WRAP_MS: Time = 1 << 32;
+
+    #[test]
+    fn transitions_continue_across_the_wrap_point() {
+        let transition = StartedTransition {
+            start_time_ms: WRAP_MS - 100,
+            transition: Transition::on_within(200),
+        };
+        assert_eq!(transition.get_current_val(WRAP_MS).brightness, 0.5);
+        assert_eq!(transition.get_current_val(WRAP_MS + 100).brightness, 1.0);
+        assert_eq!(transition.end_time(), WRAP_MS + 100);
+    }
+
+    #[test]
+    fn shots_are_poured_across_the_wrap_point() {
+        let mut sim = Simulation::new(SimplePouring);
+        sim.interface.time_ms = WRAP_MS - 500;
+        sim.machine.boot(&mut sim.interface, &mut sim.program);
+        sim.interface.pressed[Switch::Number2] = true;
+        let mut relay_on_ms = 0;
+        while sim.interface.time_ms < WRAP_MS + 5000 {
+            sim.run_for(STEP_MS);
+            sim.interface.pressed[Switch::Number2] = false;
+            if sim.interface.relay_on[0] {
+                relay_on_ms += STEP_MS;
+            }
+        }
+        assert_eq!(sim.interface.num_pours[0], 2);
+        assert!(!sim.interface.relay_on[0]);
+        let expected_ms = 2 * shot_duration(&sim);
+        assert!(relay_on_ms.abs_diff(expected_ms) <= 2 * STEP_MS);
+    }
+
+    /// Keeps the relay on and counts what reaches it.
+    #[derive(Default)]
+    struct IdleProgram {
+        relay_on: bool,
+        num_key_presses: usize,
+        num_cleanups: usize,
+        num_resumes: usize,
+    }
+
+    impl Program for IdleProgram {
+        fn update(&mut self, machine: &mut Machine, state: &State) {
+            if state.anything_just_pressed() {
+                self.num_key_presses += 1;
+            }
+            if !self.relay_on {
+                self.relay_on = true;
+                machine.set_relay_state(0, RelayState::On);
+            }
+        }
+
+        fn cleanup_before_switch(&mut self, _machine: &mut Machine) {
+            self.num_cleanups += 1;
+            self.relay_on = false;
+        }
+
+        fn on_resume(&mut self, _machine: &mut Machine) {
+            self.num_resumes += 1;
+        }
+    }
+
+    #[test]
+    fn idling_enters_the_attract_mode_until_a_key_is_pressed() {
+        let mut sim = Simulation::new(IdleProgram::default());
+        let idle_timeout = sim.machine.config().idle_timeout;
+        sim.run_for(idle_timeout - STEP_MS * 2);
+        assert!(sim.machine.attract_mode.is_none());
+        assert!(sim.interface.relay_on[0]);
+        sim.run_for(STEP_MS * 4);
+        assert!(sim.machine.attract_mode.is_some());
+        assert_eq!(sim.program.num_cleanups, 1);
+        assert!(!sim.interface.relay_on[0]);
+        // The program only continues once the key is released.
+        sim.interface.pressed[Switch::Number5] = true;
+        sim.run_for(STEP_MS * 4);
+        assert!(sim.machine.attract_mode.is_some());
+        assert_eq!(sim.program.num_resumes, 0);
+        sim.interface.pressed[Switch::Number5] = false;
+        sim.run_for(STEP_MS * 4);
+        assert!(sim.machine.attract_mode.is_none());
+        assert_eq!(sim.program.num_resumes, 1);
+        assert_eq!(sim.program.num_key_presses, 0);
+        assert!(sim.interface.relay_on[0]);
+    }
+
+    #[test]
+    fn a_timeout_of_zero_disables_the_attract_mode() {
+        let mut sim = Simulation::new(IdleProgram::default());
+        let idle_timeout = sim.machine.config().idle_timeout;
+        sim.machine.get_config_mut().idle_timeout = 0;
+        sim.run_for(idle_timeout * 2);
+        assert!(sim.machine.attract_mode.is_none());
+        assert_eq!(sim.program.num_cleanups, 0);
+        assert!(sim.interface.relay_on[0]);
+    }
+
+    fn shot_duration(sim: &Simulation<SimplePouring>) -> Duration {
+        let pump_config = sim.machine.config().pumps[0];
+        pump_config.pour_duration(pump_config.shot_volume_ml)
+    }
+
+    #[test]
+    fn holding_left_pours_from_the_second_pump() {
+        let mut sim = Simulation::new(SimplePouring);
+        sim.machine.get_config_mut().pumps[1].shot_volume_ml = 100.0;
+        sim.interface.pressed[Switch::Left] = true;
+        sim.interface.pressed[Switch::Number1] = true;
+        let relay_on_ms = sim.run_counting_relay_for(1, 5000);
+        let pump_config = sim.machine.config().pumps[1];
+        assert!(relay_on_ms.abs_diff(pump_config.pour_duration(100.0)) <= 2 * STEP_MS);
+        assert_eq!(sim.interface.num_pours, [0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn games_pour_from_the_configured_pump() {
+        let mut sim = Simulation::new(SimplePouring);
+        sim.machine.get_config_mut().game_pump = 2;
+        sim.machine.pour_with_melody(2);
+        sim.run_for(5000);
+        assert_eq!(sim.interface.num_pours, [0, 0, 2, 0]);
+    }
+
+    fn with_bottle(capacity_ml: f32) -> Simulation<SimplePouring> {
+        let mut sim = Simulation::new(SimplePouring);
+        sim.machine.get_config_mut().pumps[0].bottle_capacity_ml = Some(capacity_ml);
+        sim.machine.new_bottle(0);
+        sim
+    }
+
+    #[test]
+    fn the_bottle_level_follows_the_relay_on_time() {
+        let mut sim = with_bottle(100.0);
+        sim.press(&[Switch::Number2]);
+        sim.run_for(5000);
+        let level_ml = sim.machine.bottle_level_ml(0).unwrap();
+        assert!((58.0..=62.0).contains(&level_ml));
+        assert!(sim.interface.reports.is_empty());
+        sim.press(&[Switch::Number3]);
+        sim.run_for(5000);
+        assert_eq!(sim.machine.bottle_level_ml(0), Some(0.0));
+        assert_eq!(sim.interface.reports.len(), 1);
+        assert!(sim.interface.reports[0].starts_with("Bottle of pump 1 almost empty"));
+        sim.machine.new_bottle(0);
+        assert_eq!(sim.machine.bottle_level_ml(0), Some(100.0));
+    }
+
+    #[test]
+    fn game_pours_exceeding_the_bottle_are_refused() {
+        let mut sim = with_bottle(50.0);
+        sim.machine.pour_with_melody(3);
+        sim.run_for(5000);
+        assert_eq!(sim.interface.num_pours[0], 0);
+        assert!(sim.interface.reports[0].starts_with("Not enough left in the bottle of pump 1"));
+        sim.machine.pour_with_melody(2);
+        sim.run_for(5000);
+        assert_eq!(sim.interface.num_pours[0], 2);
+    }
+
+    fn pour_shots_with_flow_meter(pump: SimulatedPump, key: Switch) -> Simulation<SimplePouring> {
+        let mut sim = Simulation::new(SimplePouring);
+        sim.interface.pumps[0] = Some(pump);
+        sim.press(&[key]);
+        sim.run_for(10_000);
+        sim
+    }
+
+    #[test]
+    fn the_flow_meter_stops_the_pour() {
+        // Faster than calibrated, so a timed pour would be too large.
+        let sim = pour_shots_with_flow_meter(SimulatedPump::new(40.0, 1000.0), Switch::Number2);
+        let remaining_ml = sim.interface.pumps[0].as_ref().unwrap().remaining_ml;
+        assert!((960.0 - remaining_ml).abs() < 1.0);
+        assert_eq!(sim.interface.num_pours[0], 2);
+        assert!(!sim.interface.relay_on[0]);
+        assert!(sim
+            .interface
+            .reports
+            .iter()
+            .all(|report| report == "Poured 20 ml from pump 1"));
+    }
+
+    #[test]
+    fn an_empty_bottle_stops_all_shots() {
+        let sim = pour_shots_with_flow_meter(SimulatedPump::new(40.0, 30.0), Switch::Number3);
+        assert!(!sim.interface.relay_on[0]);
+        assert_eq!(sim.interface.reports[0], "Poured 20 ml from pump 1");
+        assert!(sim.interface.reports[1].starts_with("Bottle of pump 1 empty, poured 10 of"));
+        assert_eq!(sim.interface.reports.len(), 2);
+    }
+
+    #[test]
+    fn only_what_was_poured_is_counted() {
+        let sim = pour_shots_with_flow_meter(SimulatedPump::new(40.0, 30.0), Switch::Number3);
+        let total = sim.machine.statistics().total;
+        assert!((29.0..=31.0).contains(&total.volume_ml));
+        assert!((1.4..=1.6).contains(&total.num_shots));
+    }
+
+    #[test]
+    fn continuous_pouring_is_counted() {
+        let mut sim = Simulation::new(ContinuousPouring);
+        sim.interface.pressed[Switch::Number1] = true;
+        sim.run_for(1400);
+        sim.interface.pressed[Switch::Number1] = false;
+        sim.run_for(STEP_MS * 2);
+        let volume_ml = sim.machine.statistics().total.volume_ml;
+        assert!((39.0..=41.0).contains(&volume_ml));
+    }
+
+    #[test]
+    fn pours_wait_for_the_glass() {
+        let mut sim = Simulation::new(SimplePouring);
+        sim.interface.glass_present = Some(false);
+        sim.interface.pressed[Switch::Number1] = true;
+        let mut beeped = false;
+        for _ in 0..2000 / STEP_MS {
+            sim.run_for(STEP_MS);
+            beeped |= sim.interface.speaker_on;
+            assert!(!sim.interface.relay_on[0]);
+        }
+        assert!(beeped);
+        sim.interface.pressed[Switch::Number1] = false;
+        sim.interface.glass_present = Some(true);
+        let relay_on_ms = sim.run_counting_relay_for(0, 3000);
+        assert!(relay_on_ms.abs_diff(shot_duration(&sim)) <= 2 * STEP_MS);
+        assert_eq!(sim.interface.num_pours[0], 1);
+    }
+
+    #[test]
+    fn removing_the_glass_pauses_the_pour() {
+        let mut sim = Simulation::new(SimplePouring);
+        sim.interface.glass_present = Some(true);
+        sim.interface.pressed[Switch::Number1] = true;
+        let mut relay_on_ms = sim.run_counting_relay_for(0, 300);
+        sim.interface.pressed[Switch::Number1] = false;
+        sim.interface.glass_present = Some(false);
+        relay_on_ms += sim.run_counting_relay_for(0, 2000);
+        assert!(!sim.interface.relay_on[0]);
+        sim.interface.glass_present = Some(true);
+        relay_on_ms += sim.run_counting_relay_for(0, 3000);
+        assert!(relay_on_ms.abs_diff(shot_duration(&sim)) <= 3 * STEP_MS);
+        assert_eq!(sim.interface.num_pours[0], 2);
+        assert!(!sim.interface.speaker_on);
+    }
 }
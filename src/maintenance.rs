@@ -0,0 +1,255 @@
+use alloc::format;
+
+use crate::hardware_interface::RelayState;
+use crate::hardware_interface::State;
+use crate::hardware_interface::Switch;
+use crate::hardware_interface::NUM_PUMPS;
+use crate::machine::Machine;
+use crate::melody::CONFIRM_SELECTION;
+use crate::melody::ERROR;
+use crate::programs::Program;
+use crate::Duration;
+use crate::Time;
+
+/// Priming stops once the pump was on this long in total since the tube
+/// was last rinsed or drained, even if the key is still held, in case
+/// something got stuck on it.
+const MAX_PRIME_DURATION_MS: Duration = 10_000;
+const RINSE_PULSE_ON_MS: Duration = 1000;
+/// Pulses flush the tube better than a steady stream.
+const RINSE_PULSE_OFF_MS: Duration = 500;
+const RINSE_NUM_PULSES: usize = 10;
+/// Long enough to empty the tube, short enough not to run the pump dry
+/// for long.
+const DRAIN_DURATION_MS: Duration = 8000;
+
+#[derive(Clone, Copy)]
+enum MaintenanceState {
+    Idle,
+    Priming {
+        since_ms: Time,
+    },
+    /// The bottle is attached again afterwards.
+    Rinsing,
+    Draining,
+}
+
+/// Fills the tube of a pump before the first pour of the evening and
+/// cleans it after the party. The number keys select the pump, holding
+/// the left key primes it, the right key rinses it with water and key 0
+/// drains it.
+pub struct MaintenanceProgram {
+    state: MaintenanceState,
+    pump: usize,
+    primed_ms: [Duration; NUM_PUMPS],
+}
+
+impl MaintenanceProgram {
+    pub fn new(machine: &mut Machine) -> Self {
+        machine.report("Hold left to prime, press right to rinse or 0 to drain".into());
+        Self {
+            state: MaintenanceState::Idle,
+            pump: 0,
+            primed_ms: [0; NUM_PUMPS],
+        }
+    }
+
+    fn select_pump(&mut self, machine: &mut Machine, num: usize) {
+        if (1..=NUM_PUMPS).contains(&num) {
+            self.pump = num - 1;
+            machine.report(format!("Maintaining pump {}", num));
+            machine.play_melody(CONFIRM_SELECTION);
+        } else {
+            machine.play_melody(ERROR);
+        }
+    }
+
+    fn start_priming(&mut self, machine: &mut Machine) {
+        if self.primed_ms[self.pump] >= MAX_PRIME_DURATION_MS {
+            machine.report(format!(
+                "Pump {} was primed long enough, drain it before priming again",
+                self.pump + 1
+            ));
+            machine.play_melody(ERROR);
+        } else {
+            machine.set_relay_state(self.pump, RelayState::On);
+            self.state = MaintenanceState::Priming {
+                since_ms: machine.time_ms(),
+            };
+        }
+    }
+
+    fn stop_priming(&mut self, machine: &mut Machine, since_ms: Time) {
+        machine.set_relay_state(self.pump, RelayState::Off);
+        let duration = machine.time_ms() - since_ms;
+        self.primed_ms[self.pump] += duration;
+        let volume_ml = machine.config().pumps[self.pump].volume_poured_in(duration);
+        machine.primed(self.pump, volume_ml);
+        machine.report(format!(
+            "Primed pump {} with about {:.0} ml",
+            self.pump + 1,
+            volume_ml
+        ));
+        self.state = MaintenanceState::Idle;
+    }
+
+    /// The pump pulls water instead of liquor, so the bottle level stays
+    /// the same.
+    fn rinse(&mut self, machine: &mut Machine) {
+        machine.report(format!("Rinsing pump {}", self.pump + 1));
+        machine.detach_bottle(self.pump);
+        for pulse in 0..RINSE_NUM_PULSES {
+            let offset = pulse as Duration * (RINSE_PULSE_ON_MS + RINSE_PULSE_OFF_MS);
+            machine.pour_for(self.pump, offset, RINSE_PULSE_ON_MS);
+        }
+        machine.wait_for_all_actions();
+        self.primed_ms[self.pump] = 0;
+        self.state = MaintenanceState::Rinsing;
+    }
+
+    /// The pump pulls air with the bottle removed, which empties the tube.
+    fn drain(&mut self, machine: &mut Machine) {
+        machine.report(format!("Draining pump {}", self.pump + 1));
+        machine.detach_bottle(self.pump);
+        machine.pour_for(self.pump, 0, DRAIN_DURATION_MS);
+        machine.wait_for_all_actions();
+        self.primed_ms[self.pump] = 0;
+        self.state = MaintenanceState::Draining;
+    }
+}
+
+impl Program for MaintenanceProgram {
+    fn update(&mut self, machine: &mut Machine, state: &State) {
+        match self.state {
+            MaintenanceState::Idle => {
+                if state.just_pressed(Switch::Left) {
+                    self.start_priming(machine);
+                } else if state.just_pressed(Switch::Right) {
+                    self.rinse(machine);
+                } else if state.just_pressed(Switch::Number0) {
+                    self.drain(machine);
+                } else if let Some(num) = state.lowest_pressed_number_key() {
+                    self.select_pump(machine, num);
+                }
+            }
+            MaintenanceState::Priming { since_ms } => {
+                if !state.pressed(Switch::Left)
+                    || self.primed_ms[self.pump] + machine.time_ms() - since_ms
+                        >= MAX_PRIME_DURATION_MS
+                {
+                    self.stop_priming(machine, since_ms);
+                }
+            }
+            MaintenanceState::Rinsing => {
+                machine.attach_bottle(self.pump);
+                machine.report(format!(
+                    "The tube of pump {} is full of water, drain it before pouring",
+                    self.pump + 1
+                ));
+                machine.play_melody(CONFIRM_SELECTION);
+                self.state = MaintenanceState::Idle;
+            }
+            MaintenanceState::Draining => {
+                machine.attach_bottle(self.pump);
+                machine.report(format!(
+                    "Pump {} is empty, prime it before pouring",
+                    self.pump + 1
+                ));
+                machine.play_melody(CONFIRM_SELECTION);
+                self.state = MaintenanceState::Idle;
+            }
+        }
+    }
+
+    fn cleanup_before_switch(&mut self, machine: &mut Machine) {
+        match self.state {
+            MaintenanceState::Priming { since_ms } => self.stop_priming(machine, since_ms),
+            MaintenanceState::Rinsing | MaintenanceState::Draining => {
+                machine.attach_bottle(self.pump);
+                self.state = MaintenanceState::Idle;
+            }
+            MaintenanceState::Idle => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MaintenanceProgram;
+    use super::MAX_PRIME_DURATION_MS;
+    use super::RINSE_NUM_PULSES;
+    use super::RINSE_PULSE_ON_MS;
+    use crate::hardware_interface::Switch;
+    use crate::machine::Machine;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+    use crate::Duration;
+
+    fn simulation() -> Simulation<MaintenanceProgram> {
+        let mut machine = Machine::new();
+        let program = MaintenanceProgram::new(&mut machine);
+        Simulation::with_machine(machine, program)
+    }
+
+    #[test]
+    fn priming_overflow_is_left_out_of_the_first_shot() {
+        let mut sim = simulation();
+        let pump_config = sim.machine.config().pumps[0];
+        // Fills the tube and pours 25 ml into the glass.
+        let prime_duration = pump_config.pour_duration(pump_config.tube_volume_ml + 25.0);
+        sim.interface.pressed[Switch::Left] = true;
+        sim.run_for(prime_duration);
+        sim.interface.pressed[Switch::Left] = false;
+        sim.run_for(STEP_MS * 2);
+        assert!(!sim.interface.relay_on[0]);
+        sim.machine.pour_with_melody(2);
+        let relay_on_ms = sim.run_counting_relay_for(0, 5000);
+        let expected_ms = pump_config.pour_duration(2.0 * pump_config.shot_volume_ml - 25.0);
+        assert!(relay_on_ms.abs_diff(expected_ms) <= 3 * STEP_MS);
+        assert_eq!(sim.interface.num_pours[0], 2);
+    }
+
+    #[test]
+    fn priming_stops_after_the_limit() {
+        let mut sim = simulation();
+        sim.press(&[Switch::Number2]);
+        sim.interface.pressed[Switch::Left] = true;
+        let relay_on_ms = sim.run_counting_relay_for(1, 2 * MAX_PRIME_DURATION_MS);
+        assert!(relay_on_ms.abs_diff(MAX_PRIME_DURATION_MS) <= 2 * STEP_MS);
+        assert!(!sim.interface.relay_on[1]);
+    }
+
+    #[test]
+    fn the_limit_covers_repeated_priming() {
+        let mut sim = simulation();
+        sim.interface.pressed[Switch::Left] = true;
+        let mut relay_on_ms = sim.run_counting_relay_for(0, MAX_PRIME_DURATION_MS / 2);
+        for _ in 0..3 {
+            sim.interface.pressed[Switch::Left] = false;
+            sim.run_for(STEP_MS * 2);
+            sim.interface.pressed[Switch::Left] = true;
+            relay_on_ms += sim.run_counting_relay_for(0, MAX_PRIME_DURATION_MS);
+        }
+        assert!(relay_on_ms.abs_diff(MAX_PRIME_DURATION_MS) <= 4 * STEP_MS);
+        assert!(!sim.interface.relay_on[0]);
+    }
+
+    #[test]
+    fn rinsing_pulses_the_relay_and_keeps_the_bottle_level() {
+        let mut sim = simulation();
+        sim.machine.get_config_mut().pumps[0].bottle_capacity_ml = Some(700.0);
+        sim.machine.new_bottle(0);
+        sim.press(&[Switch::Right]);
+        let relay_on_ms = sim.run_counting_relay_for(0, 30_000);
+        assert_eq!(sim.interface.num_pours[0], RINSE_NUM_PULSES);
+        let expected_ms = RINSE_NUM_PULSES as Duration * RINSE_PULSE_ON_MS;
+        assert!(relay_on_ms.abs_diff(expected_ms) <= RINSE_NUM_PULSES as Duration * STEP_MS);
+        assert_eq!(sim.machine.bottle_level_ml(0), Some(700.0));
+        assert!(sim
+            .interface
+            .reports
+            .last()
+            .unwrap()
+            .starts_with("The tube of pump 1 is full of water"));
+    }
+}
@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+
 use crate::hardware_interface::Frequency;
 use crate::Duration;
 
@@ -56,6 +58,14 @@ pub struct Note {
 }
 
 impl Note {
+    pub fn new(freq: Frequency, length: Length, bpm: f32) -> Self {
+        Self {
+            freq,
+            note_length: length.as_ms(bpm) as Duration,
+            delay_after: delay_after_note_ms(bpm) as Duration,
+        }
+    }
+
     pub fn total_length(&self) -> Duration {
         self.note_length + self.delay_after
     }
@@ -63,6 +73,31 @@ impl Note {
 
 pub type Melody = [Note];
 
+const IDENTIFICATION_MELODY_BPM: f32 = 300.0;
+const IDENTIFICATION_MELODY_TEAM_FREQUENCIES: &[Frequency] = &[
+    Frequency::D4,
+    Frequency::G4,
+    Frequency::B4,
+    Frequency::F4,
+    Frequency::A4,
+];
+
+/// Generate a melody which identifies a player. The team of the player
+/// determines the pitch and their index within the team the number of
+/// beeps.
+pub fn identification_melody(team: usize, index: usize) -> Vec<Note> {
+    let freq = &IDENTIFICATION_MELODY_TEAM_FREQUENCIES
+        [team % IDENTIFICATION_MELODY_TEAM_FREQUENCIES.len()];
+    (0..=index)
+        .map(|_| Note::new(freq.clone(), Length::Sixteenth, IDENTIFICATION_MELODY_BPM))
+        .chain(core::iter::once(Note::new(
+            Frequency::BREAK,
+            Length::Half,
+            IDENTIFICATION_MELODY_BPM,
+        )))
+        .collect()
+}
+
 macro_rules! make_melody {
     ($name: ident, $bpm: literal, [$(( $note: ident, $length: literal)),* $(,)?]) => {
         pub const $name: &'static Melody =
@@ -172,34 +207,15 @@ make_melody!(
 
 #[rustfmt::skip]
 make_melody!(
-    REACTION_TESTER_PLAYER_0_MELODY_IDENTIFICATION_MELODY,
-    300.0,
+    REACTION_TESTER_TOURNAMENT_VICTORY_MELODY,
+    160.0,
     [
-        (D4, 16),
-        (BREAK, 2),
-    ]
-);
-
-#[rustfmt::skip]
-make_melody!(
-    REACTION_TESTER_PLAYER_1_MELODY_IDENTIFICATION_MELODY,
-    300.0,
-    [
-        (D4, 16),
-        (D4, 16),
-        (BREAK, 2),
-    ]
-);
-
-#[rustfmt::skip]
-make_melody!(
-    REACTION_TESTER_PLAYER_2_MELODY_IDENTIFICATION_MELODY,
-    300.0,
-    [
-        (D4, 16),
-        (D4, 16),
-        (D4, 16),
-        (BREAK, 2),
+        (C4, 8),
+        (E4, 8),
+        (G4, 8),
+        (C5, 4),
+        (G4, 8),
+        (C5, 2),
     ]
 );
 
@@ -322,6 +338,85 @@ make_melody!(
     ]
 );
 
+#[rustfmt::skip]
+make_melody!(
+    REACTION_TIMER,
+    240.0,
+    [
+        (C4, 16),
+        (BREAK, 8),
+        (C4, 16),
+        (BREAK, 8),
+        (C4, 16),
+        (BREAK, 8),
+        (C5, 4),
+    ]
+);
+
+#[rustfmt::skip]
+make_melody!(
+    SIMON_SAYS,
+    220.0,
+    [
+        (C4, 8),
+        (E4, 8),
+        (G4, 8),
+        (E4, 8),
+        (C5, 4),
+    ]
+);
+
+#[rustfmt::skip]
+make_melody!(
+    SIMON_SAYS_ROUND_COMPLETE,
+    300.0,
+    [
+        (G4, 16),
+        (C5, 8),
+        (BREAK, 4),
+    ]
+);
+
+#[rustfmt::skip]
+make_melody!(
+    SIMON_SAYS_MISTAKE,
+    150.0,
+    [
+        (G4, 8),
+        (F_SHARP_4, 8),
+        (F4, 8),
+        (E4, 2),
+    ]
+);
+
+#[rustfmt::skip]
+make_melody!(
+    RUSSIAN_ROULETTE_SPIN,
+    400.0,
+    [
+        (C4, 16),
+        (D4, 16),
+        (C4, 16),
+        (D4, 16),
+        (C4, 16),
+        (D4, 16),
+        (C4, 16),
+        (D4, 16),
+        (G4, 8),
+    ]
+);
+
+#[rustfmt::skip]
+make_melody!(
+    RUSSIAN_ROULETTE_SURVIVOR,
+    200.0,
+    [
+        (G4, 8),
+        (G4, 8),
+        (C5, 2),
+    ]
+);
+
 #[rustfmt::skip]
 make_melody!(
     CHROMATIC_SCALE,
@@ -341,3 +436,27 @@ make_melody!(
         (B4, 8),
     ]
 );
+
+#[rustfmt::skip]
+make_melody!(
+    BOTTLE_EMPTY,
+    160.0,
+    [
+        (G4, 8),
+        (E4, 8),
+        (C4, 8),
+        (C4, 2),
+    ]
+);
+
+#[rustfmt::skip]
+make_melody!(
+    BOTTLE_LOW,
+    160.0,
+    [
+        (C5, 16),
+        (A4, 16),
+        (C5, 16),
+        (A4, 16),
+    ]
+);
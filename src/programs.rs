@@ -1,15 +1,19 @@
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
 
-use rand::prelude::*;
-use rand::rngs::SmallRng;
-use rand::SeedableRng;
-
+use crate::calibration::CalibrationProgram;
+use crate::cocktails::CocktailProgram;
 use crate::configuration::ConfigurationProgram;
+use crate::configuration::Variable;
+use crate::configuration::NUM_KEYS_PER_PAGE;
 use crate::hardware_interface::Frequency;
 use crate::hardware_interface::Led;
 use crate::hardware_interface::RelayState;
 use crate::hardware_interface::State;
 use crate::hardware_interface::Switch;
+use crate::maintenance::MaintenanceProgram;
 use crate::melody::Melody;
 use crate::melody::BARBIE_GIRL;
 use crate::melody::BEETHOVEN_5;
@@ -17,27 +21,58 @@ use crate::melody::BEETHOVEN_9;
 use crate::melody::IN_PARIS;
 use crate::melody::JINGLE;
 use crate::melody::PROGRAM_SWITCHING;
-use crate::melody::RUSSIAN_ROULETTE_PLAYER_NOT_SELECTED;
-use crate::melody::RUSSIAN_ROULETTE_PLAYER_SELECTED;
+use crate::melody::REACTION_TIMER;
+use crate::melody::SIMON_SAYS;
+use crate::reaction_tester::Mode;
 use crate::reaction_tester::ReactionTester;
+use crate::russian_roulette::RussianRoulette;
+use crate::script::ScriptProgram;
+use crate::simon_says::SimonSays;
+use crate::snapshot::SnapshotReader;
+use crate::snapshot::SnapshotWriter;
+use crate::statistics::StatisticsProgram;
 use crate::Duration;
 use crate::Machine;
+use crate::Time;
 
 const PROGRAM_SWITCH_LED_ON_DURATION_MS: Duration = 500;
 const PROGRAM_SWITCH_LED_TRANSITION_DURATION_MS: Duration = 500;
 
+const BROWSE_LED_ON_DURATION_MS: Duration = 150;
+const BROWSE_LED_TRANSITION_DURATION_MS: Duration = 50;
+/// Holding left or right for this long selects the browsed program.
+const BROWSE_LONG_PRESS_DURATION_MS: Duration = 1000;
+
 pub trait Program {
     fn update(&mut self, machine: &mut Machine, state: &State);
+    /// Called when the program is paused or replaced. The state of the
+    /// program is kept, so that it can be resumed later.
     fn cleanup_before_switch(&mut self, _machine: &mut Machine) {}
+    /// Called when a paused program continues.
+    fn on_resume(&mut self, _machine: &mut Machine) {}
+    /// The state which is restored after a power loss, if any. Never
+    /// taken while pouring.
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+    /// Called on a newly constructed program with the last snapshot.
+    fn restore(&mut self, _machine: &mut Machine, _snapshot: &[u8]) {}
 }
 
+/// Pours as many shots as the number key says. Zero pours half a shot.
+/// The shots come from the first pump, or from the second one while
+/// the left key is held, e.g. for a chaser.
 pub struct SimplePouring;
 
 impl Program for SimplePouring {
     fn update(&mut self, machine: &mut Machine, state: &State) {
+        let pump = if state.pressed(Switch::Left) { 1 } else { 0 };
         for switch in state.iter_just_pressed() {
             if let Some(num) = switch.get_num() {
-                machine.pour_with_melody(num);
+                match num {
+                    0 => machine.pour_shots_with_melody(pump, 0.5),
+                    num => machine.pour_shots_with_melody(pump, num as f32),
+                }
                 machine.wait_for_all_actions();
                 return;
             }
@@ -45,122 +80,434 @@ impl Program for SimplePouring {
     }
 }
 
+/// Pours from the first pump while any key is held.
 pub struct ContinuousPouring;
 
 impl Program for ContinuousPouring {
     fn update(&mut self, machine: &mut Machine, state: &State) {
         if state.anything_pressed() {
-            machine.set_relay_state(RelayState::On);
+            machine.set_relay_state(0, RelayState::On);
             machine.set_speaker_frequency(Frequency::Some(400.0));
         } else {
-            machine.set_relay_state(RelayState::Off);
+            machine.set_relay_state(0, RelayState::Off);
             machine.set_speaker_frequency(Frequency::Silence);
         }
     }
 
     fn cleanup_before_switch(&mut self, machine: &mut Machine) {
-        machine.set_relay_state(RelayState::Off);
+        machine.set_relay_state(0, RelayState::Off);
         machine.set_speaker_frequency(Frequency::Silence);
     }
 }
 
-#[derive(Default, Debug)]
-enum RussianRouletteGameState {
-    #[default]
-    PlayerSelection,
-    AwaitingGlass,
+/// A program which can be selected in the menu.
+pub struct ProgramEntry {
+    pub id: &'static str,
+    pub melody: &'static Melody,
+    pub constructor: fn(&mut Machine) -> Box<dyn Program>,
+    /// The configuration variables which affect the program.
+    pub config_variables: &'static [Variable],
+    /// The key which selects the program unless remapped. Keys beyond
+    /// the ten number keys are on the following pages of the menu.
+    pub default_key: Option<usize>,
+    /// Overlays are shown on top of the paused program, which resumes
+    /// once the overlay is left.
+    pub overlay: bool,
+}
+
+pub const PROGRAMS: &[ProgramEntry] = &[
+    ProgramEntry {
+        id: "continuous_pouring",
+        melody: BEETHOVEN_5,
+        constructor: |_| Box::new(ContinuousPouring),
+        config_variables: &[],
+        default_key: Some(1),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "shots",
+        melody: BEETHOVEN_9,
+        constructor: |_| Box::new(SimplePouring),
+        config_variables: &[Variable::ShotVolume, Variable::PumpShotVolume],
+        default_key: Some(2),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "russian_roulette",
+        melody: IN_PARIS,
+        constructor: |machine| Box::new(RussianRoulette::new(machine)),
+        config_variables: &[
+            Variable::GamePump,
+            Variable::ShotVolume,
+            Variable::PumpShotVolume,
+            Variable::PumpBottleCapacity,
+            Variable::RussianRouletteLossProbability,
+            Variable::RussianRouletteMinNumberOfShots,
+            Variable::RussianRouletteMaxNumberOfShots,
+            Variable::RussianRouletteMode,
+            Variable::RussianRouletteNumChambers,
+            Variable::RussianRouletteNumLoaded,
+            Variable::RussianRouletteEscalationCurve,
+            Variable::RussianRouletteEscalationStep,
+            Variable::RussianRouletteMaxLossProbability,
+        ],
+        default_key: Some(3),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "reaction_tester",
+        melody: BARBIE_GIRL,
+        constructor: |machine| Box::new(ReactionTester::new(machine, Mode::Teams)),
+        config_variables: &[
+            Variable::GamePump,
+            Variable::ShotVolume,
+            Variable::PumpShotVolume,
+            Variable::PumpBottleCapacity,
+            Variable::ReactionNumberOfPlayers,
+            Variable::ReactionNumShotsLoser,
+            Variable::ReactionNumShotsEarlyStart,
+            Variable::ReactionTeamLayout,
+            Variable::ReactionTournamentFormat,
+            Variable::ReactionTournamentLength,
+            Variable::ReactionTournamentShotEscalation,
+        ],
+        default_key: Some(4),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "simon_says",
+        melody: SIMON_SAYS,
+        constructor: |machine| Box::new(SimonSays::new(machine)),
+        config_variables: &[
+            Variable::GamePump,
+            Variable::ShotVolume,
+            Variable::PumpShotVolume,
+            Variable::PumpBottleCapacity,
+            Variable::SimonSaysStartLength,
+            Variable::SimonSaysLengthIncrement,
+            Variable::SimonSaysInputTimeLimit,
+            Variable::SimonSaysMaxNumShots,
+        ],
+        default_key: Some(5),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "reaction_timer",
+        melody: REACTION_TIMER,
+        constructor: |machine| Box::new(ReactionTester::new(machine, Mode::Individual)),
+        config_variables: &[
+            Variable::GamePump,
+            Variable::ShotVolume,
+            Variable::PumpShotVolume,
+            Variable::PumpBottleCapacity,
+            Variable::ReactionNumberOfPlayers,
+            Variable::ReactionNumShotsLoser,
+            Variable::ReactionNumShotsEarlyStart,
+            Variable::ReactionTimeThreshold,
+            Variable::ReactionTeamLayout,
+            Variable::ReactionTournamentFormat,
+            Variable::ReactionTournamentLength,
+            Variable::ReactionTournamentShotEscalation,
+        ],
+        default_key: Some(6),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "script_1",
+        melody: JINGLE,
+        constructor: |machine| Box::new(ScriptProgram::new(machine, 0)),
+        config_variables: &[
+            Variable::GamePump,
+            Variable::ShotVolume,
+            Variable::PumpShotVolume,
+            Variable::PumpBottleCapacity,
+            Variable::ScriptMaxNumShots,
+        ],
+        default_key: Some(7),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "script_2",
+        melody: JINGLE,
+        constructor: |machine| Box::new(ScriptProgram::new(machine, 1)),
+        config_variables: &[
+            Variable::GamePump,
+            Variable::ShotVolume,
+            Variable::PumpShotVolume,
+            Variable::PumpBottleCapacity,
+            Variable::ScriptMaxNumShots,
+        ],
+        default_key: Some(8),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "script_3",
+        melody: JINGLE,
+        constructor: |machine| Box::new(ScriptProgram::new(machine, 2)),
+        config_variables: &[
+            Variable::GamePump,
+            Variable::ShotVolume,
+            Variable::PumpShotVolume,
+            Variable::PumpBottleCapacity,
+            Variable::ScriptMaxNumShots,
+        ],
+        default_key: Some(0),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "configuration",
+        melody: JINGLE,
+        constructor: |_| Box::new(ConfigurationProgram::default()),
+        config_variables: &[],
+        default_key: Some(9),
+        overlay: true,
+    },
+    ProgramEntry {
+        id: "calibration",
+        melody: JINGLE,
+        constructor: |machine| Box::new(CalibrationProgram::new(machine)),
+        config_variables: &[Variable::ShotVolume, Variable::PumpShotVolume],
+        default_key: Some(10),
+        overlay: true,
+    },
+    ProgramEntry {
+        id: "cocktails",
+        melody: JINGLE,
+        constructor: |machine| Box::new(CocktailProgram::new(machine)),
+        config_variables: &[],
+        default_key: Some(11),
+        overlay: false,
+    },
+    ProgramEntry {
+        id: "statistics",
+        melody: JINGLE,
+        constructor: |machine| Box::new(StatisticsProgram::new(machine)),
+        config_variables: &[],
+        default_key: Some(12),
+        overlay: true,
+    },
+    ProgramEntry {
+        id: "maintenance",
+        melody: JINGLE,
+        constructor: |machine| Box::new(MaintenanceProgram::new(machine)),
+        config_variables: &[Variable::PumpTubeVolume],
+        default_key: Some(13),
+        overlay: false,
+    },
+];
+
+struct StackedProgram {
+    entry: &'static ProgramEntry,
+    program: Box<dyn Program>,
 }
 
-#[derive(Debug)]
-struct RussianRoulette {
-    min_num_shots: usize,
-    max_num_shots: usize,
-    probability: f32,
-    state: RussianRouletteGameState,
-    rng: SmallRng,
+/// The programs are kept on a stack: the running program is on top,
+/// below it the paused program if the top is an overlay.
+pub struct ProgramSwitching {
+    in_selection_mode: bool,
+    /// The key of the program previewed in browse mode. The number
+    /// keys select programs on the page of this key.
+    browsed_key: Option<usize>,
+    browse_press_start: Option<Time>,
+    stack: Vec<StackedProgram>,
 }
 
-impl RussianRoulette {
-    fn new(machine: &Machine) -> Self {
+impl Default for ProgramSwitching {
+    fn default() -> Self {
         Self {
-            min_num_shots: machine.config().russian_roulette_min_num_shots,
-            max_num_shots: machine.config().russian_roulette_max_num_shots,
-            probability: machine.config().russian_roulette_loss_probability,
-            rng: SmallRng::seed_from_u64(machine.time_ms() as u64),
-            state: RussianRouletteGameState::default(),
+            in_selection_mode: true,
+            browsed_key: None,
+            browse_press_start: None,
+            stack: Vec::new(),
         }
     }
-    fn randomly_select_player(&mut self) -> bool {
-        self.rng.gen_bool(self.probability as f64)
+}
+
+impl ProgramSwitching {
+    fn page(&self) -> usize {
+        self.browsed_key.unwrap_or(0) / NUM_KEYS_PER_PAGE
     }
 
-    fn get_random_num_shots(&mut self) -> usize {
-        // in case of misconfiguration
-        let max_num_shots = if self.min_num_shots >= self.max_num_shots {
-            self.min_num_shots
-        } else {
-            self.max_num_shots
+    /// Left and right cycle through the programs in the order of their
+    /// keys, a long press selects the browsed program.
+    fn browse(&mut self, machine: &mut Machine, state: &State) {
+        if state.just_pressed(Switch::Left) || state.just_pressed(Switch::Right) {
+            self.browse_press_start = Some(machine.time_ms());
+        }
+        let Some(start) = self.browse_press_start else {
+            return;
         };
-        self.rng.gen_range(self.min_num_shots..=max_num_shots)
+        if state.pressed(Switch::Left) || state.pressed(Switch::Right) {
+            if machine.time_ms().saturating_sub(start) >= BROWSE_LONG_PRESS_DURATION_MS {
+                self.browse_press_start = None;
+                if let Some(entry) = self
+                    .browsed_key
+                    .and_then(|key| machine.config().program_for_key(key))
+                {
+                    self.select(machine, entry);
+                }
+            }
+        } else {
+            self.browse_press_start = None;
+            let forward = state.just_released(Switch::Right);
+            self.browse_next(machine, forward);
+        }
     }
-}
 
-impl Program for RussianRoulette {
-    fn update(&mut self, machine: &mut Machine, state: &State) {
-        match self.state {
-            RussianRouletteGameState::PlayerSelection => self.test_player_selection(machine, state),
-            RussianRouletteGameState::AwaitingGlass => self.wait_for_glass(machine, state),
+    fn browse_next(&mut self, machine: &mut Machine, forward: bool) {
+        let mut keys: Vec<usize> = machine
+            .config()
+            .program_keys
+            .iter()
+            .map(|(key, _)| *key)
+            .collect();
+        keys.sort_unstable();
+        let next = match (self.browsed_key, forward) {
+            (None, true) => keys.first(),
+            (None, false) => keys.last(),
+            (Some(current), true) => keys.iter().find(|key| **key > current).or(keys.first()),
+            (Some(current), false) => keys
+                .iter()
+                .rev()
+                .find(|key| **key < current)
+                .or(keys.last()),
+        };
+        let Some(&key) = next else {
+            return;
+        };
+        let Some(entry) = machine.config().program_for_key(key) else {
+            return;
+        };
+        self.browsed_key = Some(key);
+        machine.cancel_sounds_and_lights();
+        machine.report(format!("Key {}: {}", key, entry.id));
+        machine.play_melody(entry.melody);
+        for led in [Led::Left, Led::Right] {
+            machine.blink_led(
+                led,
+                key,
+                BROWSE_LED_TRANSITION_DURATION_MS,
+                BROWSE_LED_ON_DURATION_MS,
+            );
         }
     }
-}
 
-impl RussianRoulette {
-    fn test_player_selection(&mut self, machine: &mut Machine, state: &State) {
-        if state.anything_just_pressed() {
-            let selected = self.randomly_select_player();
-            if selected {
-                machine.play_melody(RUSSIAN_ROULETTE_PLAYER_SELECTED);
-                machine.wait_for_all_actions();
-                self.state = RussianRouletteGameState::AwaitingGlass;
-            } else {
-                machine.play_melody(RUSSIAN_ROULETTE_PLAYER_NOT_SELECTED);
+    /// Selecting the paused program resumes it, selecting an overlay
+    /// puts it on top of the paused program and selecting any other
+    /// program replaces the paused one.
+    fn select(&mut self, machine: &mut Machine, entry: &'static ProgramEntry) {
+        machine.cancel_sounds_and_lights();
+        self.in_selection_mode = false;
+        if let Some(paused) = self.stack.last_mut() {
+            if paused.entry.id == entry.id {
+                machine.report(format!("Resumed program {}", entry.id));
+                paused.program.on_resume(machine);
+                machine.play_melody(entry.melody);
                 machine.wait_for_all_actions();
+                machine.request_snapshot();
+                return;
             }
         }
+        if !entry.overlay {
+            self.stack.clear();
+        }
+        self.stack.push(StackedProgram {
+            entry,
+            program: (entry.constructor)(machine),
+        });
+        machine.report(format!("Selected program {}", entry.id));
+        if !entry.config_variables.is_empty() {
+            let variables: Vec<usize> = entry
+                .config_variables
+                .iter()
+                .map(|variable| variable.num())
+                .collect();
+            machine.report(format!("Configuration variables: {:?}", variables));
+        }
+        machine.play_melody(entry.melody);
+        machine.wait_for_all_actions();
+        machine.request_snapshot();
     }
 
-    fn wait_for_glass(&mut self, machine: &mut Machine, state: &State) {
-        if machine.no_ongoing_led_transition() {
-            machine.flash_led(Led::Left, 1000, 1000);
-            machine.flash_led(Led::Right, 1000, 1000);
+    /// Pause the running program. Leaving an overlay resumes the
+    /// program below it, otherwise the selection mode starts.
+    fn leave_program(&mut self, machine: &mut Machine) {
+        if let Some(top) = self.stack.last_mut() {
+            top.program.cleanup_before_switch(machine);
         }
-        if state.anything_just_pressed() {
-            let num_shots = self.get_random_num_shots();
-            machine.pour_with_melody(num_shots);
-            machine.wait_for_all_actions();
-            self.state = RussianRouletteGameState::PlayerSelection;
+        machine.stop_pouring();
+        machine.play_melody(PROGRAM_SWITCHING);
+        machine.request_snapshot();
+        if self.stack.last().is_some_and(|top| top.entry.overlay) {
+            self.stack.pop();
+            if let Some(paused) = self.stack.last_mut() {
+                machine.report(format!("Resumed program {}", paused.entry.id));
+                paused.program.on_resume(machine);
+                return;
+            }
         }
+        self.in_selection_mode = true;
+        self.browsed_key = None;
+        self.browse_press_start = None;
     }
 }
 
-pub struct ProgramSwitching {
-    in_selection_mode: bool,
-    program: Box<dyn Program>,
-}
+impl Program for ProgramSwitching {
+    fn cleanup_before_switch(&mut self, machine: &mut Machine) {
+        if !self.in_selection_mode {
+            if let Some(top) = self.stack.last_mut() {
+                top.program.cleanup_before_switch(machine);
+            }
+        }
+    }
 
-impl Default for ProgramSwitching {
-    fn default() -> Self {
-        Self {
-            in_selection_mode: true,
-            program: Box::new(ContinuousPouring),
+    fn on_resume(&mut self, machine: &mut Machine) {
+        if !self.in_selection_mode {
+            if let Some(top) = self.stack.last_mut() {
+                top.program.on_resume(machine);
+            }
+        }
+    }
+
+    /// The program at the bottom of the stack, overlays are not kept.
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        let bottom = self.stack.first().filter(|bottom| !bottom.entry.overlay)?;
+        let mut writer = SnapshotWriter::default();
+        writer.write_str(bottom.entry.id);
+        writer.write_bool(self.in_selection_mode || self.stack.len() > 1);
+        writer.write_bytes(&bottom.program.snapshot().unwrap_or_default());
+        Some(writer.finish())
+    }
+
+    fn restore(&mut self, machine: &mut Machine, snapshot: &[u8]) {
+        let mut reader = SnapshotReader::new(snapshot);
+        let (Some(id), Some(paused), Some(program_snapshot)) =
+            (reader.read_str(), reader.read_bool(), reader.read_bytes())
+        else {
+            return;
+        };
+        let Some(entry) = PROGRAMS
+            .iter()
+            .find(|entry| entry.id == id && !entry.overlay)
+        else {
+            return;
+        };
+        let mut program = (entry.constructor)(machine);
+        if !program_snapshot.is_empty() {
+            program.restore(machine, program_snapshot);
+        }
+        self.stack = vec![StackedProgram { entry, program }];
+        self.in_selection_mode = paused;
+        machine.report(format!("Restored program {}", entry.id));
+        if !paused {
+            machine.play_melody(entry.melody);
+            machine.wait_for_all_actions();
         }
     }
-}
 
-impl Program for ProgramSwitching {
     fn update(&mut self, machine: &mut Machine, state: &State) {
         if self.in_selection_mode {
-            if machine.no_ongoing_led_transition() {
+            if self.browsed_key.is_none() && machine.no_ongoing_led_transition() {
                 machine.flash_led(
                     Led::Left,
                     PROGRAM_SWITCH_LED_TRANSITION_DURATION_MS,
@@ -172,33 +519,233 @@ impl Program for ProgramSwitching {
                     PROGRAM_SWITCH_LED_ON_DURATION_MS,
                 );
             }
-            for switch in state.iter_just_pressed() {
-                if let Some((melody, program)) = program_num(switch, machine) {
-                    self.program = program;
-                    self.in_selection_mode = false;
-                    machine.play_melody(melody);
-                    machine.wait_for_all_actions();
+            self.browse(machine, state);
+            if let Some(key) = state.lowest_pressed_number_key() {
+                let slot = self.page() * NUM_KEYS_PER_PAGE + key;
+                if let Some(entry) = machine.config().program_for_key(slot) {
+                    self.select(machine, entry);
                 }
             }
         } else {
-            if state.pressed(Switch::Left) && state.pressed(Switch::Right) {
-                self.program.cleanup_before_switch(machine);
-                machine.play_melody(PROGRAM_SWITCHING);
-                self.in_selection_mode = true;
+            if state.left_and_right_just_pressed() {
+                self.leave_program(machine);
+            } else if let Some(top) = self.stack.last_mut() {
+                machine.set_current_program(top.entry.id);
+                top.program.update(machine, state);
             } else {
-                self.program.update(machine, state);
+                self.in_selection_mode = true;
             }
         }
     }
 }
 
-fn program_num(switch: Switch, machine: &Machine) -> Option<(&'static Melody, Box<dyn Program>)> {
-    match switch {
-        Switch::Number1 => Some((BEETHOVEN_5, Box::new(ContinuousPouring))),
-        Switch::Number2 => Some((BEETHOVEN_9, Box::new(SimplePouring))),
-        Switch::Number3 => Some((IN_PARIS, Box::new(RussianRoulette::new(machine)))),
-        Switch::Number4 => Some((BARBIE_GIRL, Box::new(ReactionTester::new(machine)))),
-        Switch::Number9 => Some((JINGLE, Box::new(ConfigurationProgram::default()))),
-        _ => None,
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::ProgramSwitching;
+    use super::BROWSE_LONG_PRESS_DURATION_MS;
+    use super::PROGRAMS;
+    use crate::configuration::RussianRouletteMode;
+    use crate::configuration::NUM_KEYS_PER_PAGE;
+    use crate::hardware_interface::Switch;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+    use crate::Duration;
+
+    /// Long enough for every melody and pour to finish.
+    const SETTLE_DURATION_MS: Duration = 60_000;
+
+    fn press_left_and_right(sim: &mut Simulation<ProgramSwitching>) {
+        sim.interface.pressed[Switch::Left] = true;
+        sim.press(&[Switch::Right]);
+        sim.interface.pressed[Switch::Left] = false;
+    }
+
+    fn running_program(sim: &Simulation<ProgramSwitching>) -> Option<&'static str> {
+        if sim.program.in_selection_mode {
+            None
+        } else {
+            sim.program.stack.last().map(|top| top.entry.id)
+        }
+    }
+
+    #[test]
+    fn every_program_can_be_selected_and_exited() {
+        for entry in PROGRAMS.iter() {
+            let mut sim = Simulation::new(ProgramSwitching::default());
+            let slot = sim
+                .machine
+                .config()
+                .program_keys
+                .iter()
+                .find(|(_, id)| *id == entry.id)
+                .map(|(slot, _)| *slot)
+                .unwrap_or_else(|| panic!("{} has no key", entry.id));
+            // Browsing to a program on another page switches to that page.
+            while sim.program.page() != slot / NUM_KEYS_PER_PAGE {
+                sim.press(&[Switch::Right]);
+                sim.run_for(SETTLE_DURATION_MS);
+            }
+            sim.press(&[Switch::number(slot % NUM_KEYS_PER_PAGE)]);
+            sim.run_for(SETTLE_DURATION_MS);
+            assert!(!sim.program.in_selection_mode, "{}", entry.id);
+            for num in 0..10 {
+                sim.interface.pressed[Switch::number(num)] = true;
+                sim.run_for(SETTLE_DURATION_MS);
+                sim.interface.pressed[Switch::number(num)] = false;
+                sim.run_for(SETTLE_DURATION_MS);
+            }
+            press_left_and_right(&mut sim);
+            sim.run_for(SETTLE_DURATION_MS);
+            assert!(sim.program.in_selection_mode, "{}", entry.id);
+            assert!(!sim.interface.relay_on[0], "{}", entry.id);
+            assert!(!sim.interface.speaker_on, "{}", entry.id);
+        }
+    }
+
+    #[test]
+    fn browsing_selects_the_previewed_program() {
+        let mut sim = Simulation::new(ProgramSwitching::default());
+        let mut browsed_ids = Vec::new();
+        for _ in 0..PROGRAMS.len() {
+            sim.press(&[Switch::Right]);
+            let key = sim.program.browsed_key.unwrap();
+            browsed_ids.push(sim.machine.config().program_for_key(key).unwrap().id);
+            sim.run_for(SETTLE_DURATION_MS);
+        }
+        let mut all_ids: Vec<_> = PROGRAMS.iter().map(|entry| entry.id).collect();
+        all_ids.sort_unstable();
+        browsed_ids.sort_unstable();
+        assert_eq!(browsed_ids, all_ids);
+        sim.press(&[Switch::Left]);
+        assert!(sim.program.in_selection_mode);
+        sim.interface.pressed[Switch::Left] = true;
+        sim.run_for(BROWSE_LONG_PRESS_DURATION_MS + STEP_MS);
+        sim.interface.pressed[Switch::Left] = false;
+        assert!(!sim.program.in_selection_mode);
+    }
+
+    #[test]
+    fn leaving_an_overlay_resumes_the_paused_program() {
+        let mut sim = Simulation::new(ProgramSwitching::default());
+        sim.press(&[Switch::number(4)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(running_program(&sim), Some("reaction_tester"));
+        press_left_and_right(&mut sim);
+        assert_eq!(running_program(&sim), None);
+        sim.press(&[Switch::number(9)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(running_program(&sim), Some("configuration"));
+        press_left_and_right(&mut sim);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(running_program(&sim), Some("reaction_tester"));
+        assert_eq!(sim.program.stack.len(), 1);
+    }
+
+    #[test]
+    fn selecting_another_program_replaces_the_paused_one() {
+        let mut sim = Simulation::new(ProgramSwitching::default());
+        sim.press(&[Switch::number(4)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        press_left_and_right(&mut sim);
+        sim.press(&[Switch::number(5)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(running_program(&sim), Some("simon_says"));
+        assert_eq!(sim.program.stack.len(), 1);
+    }
+
+    #[test]
+    fn relay_is_off_after_every_transition() {
+        let mut sim = Simulation::new(ProgramSwitching::default());
+        sim.press(&[Switch::number(1)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        // Pause continuous pouring while pouring.
+        sim.interface.pressed[Switch::number(5)] = true;
+        sim.run_for(STEP_MS * 4);
+        assert!(sim.interface.relay_on[0]);
+        press_left_and_right(&mut sim);
+        assert!(!sim.interface.relay_on[0]);
+        // Open the configuration on top and come back.
+        sim.press(&[Switch::number(9)]);
+        assert!(!sim.interface.relay_on[0]);
+        sim.interface.pressed[Switch::number(5)] = false;
+        sim.run_for(SETTLE_DURATION_MS);
+        press_left_and_right(&mut sim);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(running_program(&sim), Some("continuous_pouring"));
+        assert!(!sim.interface.relay_on[0]);
+        // Try to leave the shots program while it pours.
+        press_left_and_right(&mut sim);
+        sim.press(&[Switch::number(2)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        sim.press(&[Switch::number(3)]);
+        press_left_and_right(&mut sim);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert!(!sim.interface.relay_on[0]);
+        press_left_and_right(&mut sim);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert!(sim.program.in_selection_mode);
+        assert!(!sim.interface.relay_on[0]);
+    }
+
+    #[test]
+    fn a_missing_glass_does_not_lock_the_program() {
+        let mut sim = Simulation::new(ProgramSwitching::default());
+        sim.press(&[Switch::number(2)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        sim.interface.glass_present = Some(false);
+        sim.press(&[Switch::number(3)]);
+        sim.run_for(1000);
+        press_left_and_right(&mut sim);
+        sim.run_for(STEP_MS * 2);
+        assert_eq!(running_program(&sim), None);
+        // The pour was dropped, not just held back.
+        sim.interface.glass_present = Some(true);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(sim.interface.num_pours[0], 0);
+        assert!(!sim.interface.speaker_on);
+    }
+
+    #[test]
+    fn the_revolver_can_be_left_while_it_spins() {
+        let mut sim = Simulation::new(ProgramSwitching::default());
+        sim.machine.get_config_mut().russian_roulette_mode = RussianRouletteMode::Revolver;
+        sim.press(&[Switch::number(3)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        // Play without registering players.
+        sim.press(&[Switch::Right]);
+        sim.run_for(SETTLE_DURATION_MS);
+        // The left key spins the cylinder before the right one follows.
+        sim.interface.pressed[Switch::Left] = true;
+        sim.run_for(STEP_MS * 4);
+        sim.press(&[Switch::Right]);
+        sim.interface.pressed[Switch::Left] = false;
+        sim.run_for(STEP_MS * 2);
+        assert_eq!(running_program(&sim), None);
+    }
+
+    #[test]
+    fn the_last_program_is_restored_at_boot() {
+        let mut sim = Simulation::new(ProgramSwitching::default());
+        sim.press(&[Switch::number(3)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        // Open the configuration on top, which is not restored.
+        press_left_and_right(&mut sim);
+        sim.press(&[Switch::number(9)]);
+        sim.run_for(SETTLE_DURATION_MS);
+
+        let mut rebooted = Simulation::new(ProgramSwitching::default());
+        rebooted.interface.storage = sim.interface.storage.clone();
+        rebooted
+            .machine
+            .boot(&mut rebooted.interface, &mut rebooted.program);
+        assert!(rebooted.program.in_selection_mode);
+        assert_eq!(rebooted.program.stack.len(), 1);
+        assert_eq!(rebooted.program.stack[0].entry.id, "russian_roulette");
+        rebooted.press(&[Switch::number(3)]);
+        rebooted.run_for(SETTLE_DURATION_MS);
+        assert_eq!(running_program(&rebooted), Some("russian_roulette"));
+        assert!(!rebooted.interface.relay_on[0]);
     }
 }
@@ -0,0 +1,80 @@
+use rand::rngs::SmallRng;
+use rand::RngCore;
+use rand::SeedableRng;
+
+/// Arbitrary odd constant used for mixing new entropy into the pool
+/// (the golden ratio, as in splitmix64).
+const MIXING_CONSTANT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// The random number generator shared by all programs. Entropy from
+/// the hardware and the timing of button presses is mixed into a
+/// pool which regularly reseeds the generator, so that games do not
+/// depend on when exactly they were selected.
+pub struct Random {
+    rng: SmallRng,
+    pool: u64,
+}
+
+impl Random {
+    /// A deterministic generator, for example for tests.
+    pub fn from_seed(seed: u64) -> Self {
+        Self {
+            rng: SmallRng::seed_from_u64(seed),
+            pool: seed,
+        }
+    }
+
+    pub fn add_entropy(&mut self, entropy: u64) {
+        self.pool = (self.pool ^ entropy)
+            .wrapping_mul(MIXING_CONSTANT)
+            .rotate_left(31);
+    }
+
+    pub fn reseed(&mut self) {
+        self.rng = SmallRng::seed_from_u64(self.rng.next_u64() ^ self.pool);
+    }
+}
+
+impl RngCore for Random {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
+
+    use super::Random;
+
+    #[test]
+    fn same_seed_gives_same_numbers() {
+        let mut a = Random::from_seed(42);
+        let mut b = Random::from_seed(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn entropy_changes_numbers() {
+        let mut a = Random::from_seed(42);
+        let mut b = Random::from_seed(42);
+        b.add_entropy(1);
+        a.reseed();
+        b.reseed();
+        assert_ne!(a.next_u64(), b.next_u64());
+    }
+}
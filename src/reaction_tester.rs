@@ -1,134 +1,295 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
 
-use rand::rngs::SmallRng;
 use rand::Rng;
-use rand::SeedableRng;
 
 use crate::hardware_interface::Led;
 use crate::hardware_interface::State;
 use crate::hardware_interface::Switch;
 use crate::machine::Machine;
+use crate::melody::identification_melody;
+use crate::melody::CONFIRM_SELECTION;
 use crate::melody::REACTION_TESTER_EARLY_START_MELODY;
 use crate::melody::REACTION_TESTER_GAME_BEGINS_MELODY;
-use crate::melody::REACTION_TESTER_PLAYER_0_MELODY_IDENTIFICATION_MELODY;
-use crate::melody::REACTION_TESTER_PLAYER_1_MELODY_IDENTIFICATION_MELODY;
-use crate::melody::REACTION_TESTER_PLAYER_2_MELODY_IDENTIFICATION_MELODY;
 use crate::melody::REACTION_TESTER_TEAM_WON_MELODY;
+use crate::melody::REACTION_TESTER_TOURNAMENT_VICTORY_MELODY;
 use crate::melody::REACTION_TESTER_WAIT_FOR_REACTION_MELODY;
 use crate::programs::Program;
+use crate::snapshot::SnapshotReader;
+use crate::snapshot::SnapshotWriter;
+use crate::tournament::Tournament;
 use crate::Duration;
+use crate::DurationUs;
 use crate::Time;
+use crate::TimeUs;
 
-const MIN_REACTION_DURATION_MS: u32 = 5000;
-const MAX_REACTION_DURATION_MS: u32 = 15000;
+const MIN_REACTION_DURATION_MS: Duration = 5000;
+const MAX_REACTION_DURATION_MS: Duration = 15000;
 
 const LED_ON_DURATION: Duration = 200;
 const LED_FLASH_DURATION: Duration = 500;
 
-const MAX_NUM_PLAYERS: usize = 6;
+/// Players who did not press their button within this time after the
+/// start signal are counted with this reaction time.
+const MAX_REACTION_TIME_MS: Duration = 5000;
 
-#[derive(Debug)]
-struct TeamState {
-    players_pressed: Vec<bool>,
-}
-
-impl TeamState {
-    fn new(num_players: usize) -> TeamState {
-        TeamState {
-            players_pressed: (0..num_players).map(|_| false).collect(),
-        }
-    }
-
-    fn won(&self) -> bool {
-        self.players_pressed.iter().all(|x| *x)
-    }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// The team which presses all buttons first wins.
+    Teams,
+    /// Every player's reaction time is measured individually.
+    Individual,
 }
 
+#[derive(Clone, Copy)]
 enum Reason {
     SlowReaction,
-    EarlyStart(usize),
+    EarlyStart(Player),
+    SlowIndividualReaction(Player),
 }
 
+#[allow(clippy::enum_variant_names)]
 enum GameState {
     WaitForStart,
     WaitForTiming(Time),
-    WaitForAllButtonPresses(TeamState, TeamState),
-    WaitForGlass { reason: Reason, team: Team },
-}
-
-#[derive(Debug)]
-enum Team {
-    Left,
-    Right,
+    /// When each player pressed their button.
+    WaitForAllButtonPresses(Vec<Option<TimeUs>>),
+    WaitForReactions(TimeUs),
+    WaitForGlass {
+        reason: Reason,
+        team: usize,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 struct Player {
     button_num: usize,
+    team: usize,
+    /// The position of the player within their team.
     index: usize,
 }
 
-const PLAYERS_LEFT_SIDE: &[Player] = &[
-    Player {
-        button_num: 1,
-        index: 0,
-    },
-    Player {
-        button_num: 4,
-        index: 1,
-    },
-    Player {
-        button_num: 7,
-        index: 2,
-    },
-];
+struct LeaderboardEntry {
+    button_num: usize,
+    best: DurationUs,
+    num_rounds: usize,
+}
 
-const PLAYERS_RIGHT_SIDE: &[Player] = &[
-    Player {
-        button_num: 3,
-        index: 0,
-    },
-    Player {
-        button_num: 6,
-        index: 1,
-    },
-    Player {
-        button_num: 9,
-        index: 2,
-    },
-];
+/// Keeps the best reaction time of each player for as long as the
+/// program is running.
+#[derive(Default)]
+struct Leaderboard {
+    entries: Vec<LeaderboardEntry>,
+}
+
+impl Leaderboard {
+    fn record(&mut self, button_num: usize, time: DurationUs) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.button_num == button_num)
+        {
+            Some(entry) => {
+                entry.best = entry.best.min(time);
+                entry.num_rounds += 1;
+            }
+            None => self.entries.push(LeaderboardEntry {
+                button_num,
+                best: time,
+                num_rounds: 1,
+            }),
+        }
+        self.entries.sort_by_key(|entry| entry.best);
+    }
+
+    fn snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_usize(self.entries.len());
+        for entry in self.entries.iter() {
+            writer.write_usize(entry.button_num);
+            writer.write_u64(entry.best);
+            writer.write_usize(entry.num_rounds);
+        }
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Option<Self> {
+        let num_entries = reader.read_usize()?;
+        let entries = (0..num_entries)
+            .map(|_| {
+                Some(LeaderboardEntry {
+                    button_num: reader.read_usize()?,
+                    best: reader.read_u64()?,
+                    num_rounds: reader.read_usize()?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some(Self { entries })
+    }
+
+    fn report(&self, machine: &mut Machine) {
+        machine.report("Leaderboard:".into());
+        for (rank, entry) in self.entries.iter().enumerate() {
+            machine.report(format!(
+                "{}. Player {}: {} ({} rounds)",
+                rank + 1,
+                entry.button_num,
+                format_reaction_time(entry.best),
+                entry.num_rounds
+            ));
+        }
+    }
+}
+
+fn format_reaction_time(time_us: DurationUs) -> String {
+    format!("{}.{:03} ms", time_us / 1000, time_us % 1000)
+}
 
 pub struct ReactionTester {
-    num_players: usize,
+    mode: Mode,
+    players: Vec<Player>,
+    num_teams: usize,
     state: GameState,
-    rng: SmallRng,
+    reaction_times: Vec<Option<DurationUs>>,
+    leaderboard: Leaderboard,
+    losers: Vec<Player>,
+    tournament: Option<Tournament>,
+    early_starts: Vec<usize>,
 }
 
-fn get_wait_for_start_state_with_random_timing(machine: &Machine, rng: &mut SmallRng) -> GameState {
+fn get_wait_for_start_state_with_random_timing(machine: &mut Machine) -> GameState {
     let time = machine.time_ms();
-    let duration = rng.gen_range(MIN_REACTION_DURATION_MS..MAX_REACTION_DURATION_MS);
+    let duration = machine
+        .rng()
+        .gen_range(MIN_REACTION_DURATION_MS..MAX_REACTION_DURATION_MS);
     GameState::WaitForTiming(time + duration)
 }
 
+/// The first team is shown on the left LED, the last team on the
+/// right LED and all teams in between on both.
+fn team_leds(team: usize, num_teams: usize) -> &'static [Led] {
+    if num_teams > 1 && team == 0 {
+        &[Led::Left]
+    } else if num_teams > 1 && team == num_teams - 1 {
+        &[Led::Right]
+    } else {
+        &[Led::Left, Led::Right]
+    }
+}
+
 impl ReactionTester {
-    pub fn new(machine: &Machine) -> Self {
-        let num_players = machine.config().num_players.min(MAX_NUM_PLAYERS);
+    pub fn new(machine: &Machine, mode: Mode) -> Self {
+        let layout = machine.config().reaction_team_layout();
+        let players = layout
+            .teams
+            .iter()
+            .enumerate()
+            .flat_map(|(team, buttons)| {
+                buttons
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, button_num)| Player {
+                        button_num: *button_num,
+                        team,
+                        index,
+                    })
+            })
+            .collect::<Vec<_>>();
+        let names = match mode {
+            Mode::Teams => (0..layout.teams.len())
+                .map(|team| format!("Team {}", team + 1))
+                .collect(),
+            Mode::Individual => players
+                .iter()
+                .map(|player| format!("Player {}", player.button_num))
+                .collect(),
+        };
+        let config = machine.config();
+        let tournament = config.reaction_tournament_format.map(|format| {
+            Tournament::new(
+                format,
+                config.reaction_tournament_length,
+                config.reaction_tournament_shot_escalation,
+                names,
+            )
+        });
         Self {
-            num_players,
-            rng: SmallRng::seed_from_u64(machine.time_ms() as u64),
+            mode,
+            early_starts: vec![0; players.len()],
+            players,
+            num_teams: layout.teams.len(),
             state: GameState::WaitForStart,
+            reaction_times: vec![],
+            leaderboard: Leaderboard::default(),
+            losers: vec![],
+            tournament,
         }
     }
 
-    fn iter_active_players(&self) -> impl Iterator<Item = (&'static Player, Team)> {
-        PLAYERS_LEFT_SIDE[..self.num_players_left()]
+    fn player_num(&self, player: &Player) -> usize {
+        self.players
             .iter()
-            .map(|player| (player, Team::Left))
-            .chain(
-                PLAYERS_RIGHT_SIDE[..self.num_players_right()]
-                    .iter()
-                    .map(|player| (player, Team::Right)),
-            )
+            .position(|other| other.button_num == player.button_num)
+            .unwrap()
+    }
+
+    /// Teams compete against each other in the team mode, players in
+    /// the individual mode.
+    fn participant(&self, player: &Player) -> usize {
+        match self.mode {
+            Mode::Teams => player.team,
+            Mode::Individual => self.player_num(player),
+        }
+    }
+
+    fn record_round(&mut self, losers: &[usize]) {
+        if let Some(tournament) = &mut self.tournament {
+            tournament.record_round(losers);
+        }
+    }
+
+    fn finish_round(&mut self, machine: &mut Machine) -> GameState {
+        if let Some(tournament) = &mut self.tournament {
+            tournament.report_scores(machine);
+            if tournament.is_over() {
+                machine.play_melody(REACTION_TESTER_TOURNAMENT_VICTORY_MELODY);
+                for winner in tournament.winners() {
+                    machine.report(format!("{} wins the tournament!", tournament.name(winner)));
+                }
+                for (player, early_starts) in self.players.iter().zip(self.early_starts.iter()) {
+                    machine.report(format!(
+                        "Player {}: {} early starts",
+                        player.button_num, early_starts
+                    ));
+                }
+                self.reset_tournament();
+            }
+        }
+        GameState::WaitForStart
+    }
+
+    /// Returns `None` if the snapshot does not fit the players.
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Option<()> {
+        let leaderboard = Leaderboard::restore(reader)?;
+        let early_starts = (0..reader.read_usize()?)
+            .map(|_| reader.read_usize())
+            .collect::<Option<Vec<_>>>()?;
+        if early_starts.len() != self.players.len() {
+            return None;
+        }
+        if reader.read_bool()? {
+            self.tournament.as_mut()?.restore(reader)?;
+        }
+        self.leaderboard = leaderboard;
+        self.early_starts = early_starts;
+        Some(())
+    }
+
+    fn reset_tournament(&mut self) {
+        if let Some(tournament) = &mut self.tournament {
+            tournament.reset();
+        }
+        self.early_starts.iter_mut().for_each(|num| *num = 0);
     }
 
     fn wait_for_start(
@@ -138,134 +299,265 @@ impl ReactionTester {
         timing: Time,
     ) -> Option<GameState> {
         let current_time = machine.time_ms();
-        for (player, team) in self.iter_active_players() {
-            if state.pressed(Switch::number(player.button_num)) {
-                machine.play_melody(REACTION_TESTER_EARLY_START_MELODY);
-                return Some(GameState::WaitForGlass {
-                    team,
-                    reason: Reason::EarlyStart(player.index),
-                });
-            }
+        let early_starter = self
+            .players
+            .iter()
+            .find(|player| state.pressed(Switch::number(player.button_num)))
+            .copied();
+        if let Some(player) = early_starter {
+            machine.play_melody(REACTION_TESTER_EARLY_START_MELODY);
+            let player_num = self.player_num(&player);
+            self.early_starts[player_num] += 1;
+            self.record_round(&[self.participant(&player)]);
+            return Some(GameState::WaitForGlass {
+                team: player.team,
+                reason: Reason::EarlyStart(player),
+            });
         }
         if current_time > timing {
             machine.play_melody(REACTION_TESTER_WAIT_FOR_REACTION_MELODY);
-            Some(GameState::WaitForAllButtonPresses(
-                TeamState::new(self.num_players_left()),
-                TeamState::new(self.num_players_right()),
-            ))
+            match self.mode {
+                Mode::Teams => Some(GameState::WaitForAllButtonPresses(vec![
+                    None;
+                    self.players.len()
+                ])),
+                Mode::Individual => {
+                    self.reaction_times = vec![None; self.players.len()];
+                    Some(GameState::WaitForReactions(machine.time_us()))
+                }
+            }
+        } else {
+            None
+        }
+    }
+
+    /// When the last player of the team pressed their button, if all
+    /// of them did.
+    fn team_finish_time(&self, team: usize, press_times: &[Option<TimeUs>]) -> Option<TimeUs> {
+        self.players
+            .iter()
+            .zip(press_times.iter())
+            .filter(|(player, _)| player.team == team)
+            .try_fold(0, |latest, (_, press_time)| {
+                Some(latest.max((*press_time)?))
+            })
+    }
+
+    /// The last team to press all of its buttons loses. If the last
+    /// teams finish within the same update, the edge times of the
+    /// buttons decide, and on an exact tie the team with the highest
+    /// number loses.
+    fn wait_for_button_presses(
+        &mut self,
+        machine: &mut Machine,
+        state: &State,
+        press_times: &mut [Option<TimeUs>],
+    ) -> Option<GameState> {
+        let finished_before: Vec<bool> = (0..self.num_teams)
+            .map(|team| self.team_finish_time(team, press_times).is_some())
+            .collect();
+        for (player, press_time) in self.players.iter().zip(press_times.iter_mut()) {
+            if press_time.is_none() {
+                *press_time = state.press_time_us(Switch::number(player.button_num));
+            }
+        }
+        let unfinished: Vec<usize> = (0..self.num_teams)
+            .filter(|team| self.team_finish_time(*team, press_times).is_none())
+            .collect();
+        let loser = match unfinished[..] {
+            [] => (0..self.num_teams)
+                .filter(|team| !finished_before[*team])
+                .max_by_key(|team| self.team_finish_time(*team, press_times)),
+            [team] => Some(team),
+            _ => None,
+        };
+        loser.map(|team| {
+            machine.play_melody(REACTION_TESTER_TEAM_WON_MELODY);
+            self.record_round(&[team]);
+            GameState::WaitForGlass {
+                team,
+                reason: Reason::SlowReaction,
+            }
+        })
+    }
+
+    fn wait_for_reactions(
+        &mut self,
+        machine: &mut Machine,
+        state: &State,
+        start_us: TimeUs,
+    ) -> Option<GameState> {
+        for (player, reaction_time) in self.players.iter().zip(self.reaction_times.iter_mut()) {
+            if reaction_time.is_none() {
+                *reaction_time = state
+                    .press_time_us(Switch::number(player.button_num))
+                    .map(|press_time| press_time.saturating_sub(start_us));
+            }
+        }
+        let time_since_start_us = machine.time_us().saturating_sub(start_us);
+        if self.reaction_times.iter().all(Option::is_some)
+            || time_since_start_us > MAX_REACTION_TIME_MS as DurationUs * 1000
+        {
+            Some(self.evaluate_reaction_times(machine))
         } else {
             None
         }
     }
 
+    fn evaluate_reaction_times(&mut self, machine: &mut Machine) -> GameState {
+        let times: Vec<DurationUs> = self
+            .reaction_times
+            .iter()
+            .map(|time| time.unwrap_or(MAX_REACTION_TIME_MS as DurationUs * 1000))
+            .collect();
+        for (player, time) in self.players.iter().zip(times.iter()) {
+            self.leaderboard.record(player.button_num, *time);
+            machine.report(format!(
+                "Player {}: {}",
+                player.button_num,
+                format_reaction_time(*time)
+            ));
+        }
+        self.leaderboard.report(machine);
+        let slowest = times.iter().copied().max().unwrap_or(0);
+        let threshold = machine.config().reaction_time_threshold as DurationUs * 1000;
+        // Reverse so that popping yields the losers in order of their buttons.
+        self.losers = self
+            .players
+            .iter()
+            .zip(times.iter())
+            .filter(|(_, time)| **time == slowest || **time > threshold)
+            .map(|(player, _)| *player)
+            .rev()
+            .collect();
+        let losers: Vec<usize> = self
+            .losers
+            .iter()
+            .map(|player| self.player_num(player))
+            .collect();
+        self.record_round(&losers);
+        machine.play_melody(REACTION_TESTER_TEAM_WON_MELODY);
+        self.next_loser(machine)
+    }
+
+    fn next_loser(&mut self, machine: &mut Machine) -> GameState {
+        match self.losers.pop() {
+            Some(player) => GameState::WaitForGlass {
+                reason: Reason::SlowIndividualReaction(player),
+                team: player.team,
+            },
+            None => self.finish_round(machine),
+        }
+    }
+
     fn wait_for_glass(&mut self, machine: &mut Machine, state: &State) {
-        let GameState::WaitForGlass { reason, team } = &self.state else {
+        let GameState::WaitForGlass { reason, team } = self.state else {
             unreachable!()
         };
-        let num_shots = match reason {
-            Reason::SlowReaction => machine.config().reaction_num_shots_loser,
-            Reason::EarlyStart(_) => machine.config().reaction_num_shots_early_start,
-        };
-        let led = match team {
-            Team::Left => Led::Left,
-            Team::Right => Led::Right,
+        let (num_shots, participant) = match reason {
+            Reason::SlowReaction => (machine.config().reaction_num_shots_loser, team),
+            Reason::SlowIndividualReaction(player) => (
+                machine.config().reaction_num_shots_loser,
+                self.participant(&player),
+            ),
+            Reason::EarlyStart(player) => (
+                machine.config().reaction_num_shots_early_start,
+                self.participant(&player),
+            ),
         };
+        let extra_shots = self
+            .tournament
+            .as_ref()
+            .map(|tournament| tournament.extra_shots(participant))
+            .unwrap_or(0);
         if machine.no_ongoing_led_transition() {
-            machine.flash_led(led, LED_FLASH_DURATION, LED_ON_DURATION);
+            for led in team_leds(team, self.num_teams) {
+                machine.flash_led(*led, LED_FLASH_DURATION, LED_ON_DURATION);
+            }
         }
         if machine.no_sound_queued() {
-            if let Reason::EarlyStart(player) = reason {
-                match player {
-                    0 => machine.play_melody(REACTION_TESTER_PLAYER_0_MELODY_IDENTIFICATION_MELODY),
-                    1 => machine.play_melody(REACTION_TESTER_PLAYER_1_MELODY_IDENTIFICATION_MELODY),
-                    2 => machine.play_melody(REACTION_TESTER_PLAYER_2_MELODY_IDENTIFICATION_MELODY),
-                    _ => unreachable!(),
-                }
+            if let Reason::EarlyStart(player) | Reason::SlowIndividualReaction(player) = reason {
+                machine.play_melody(&identification_melody(player.team, player.index));
             }
         }
-        if state.anything_just_pressed() {
-            let num_shots = num_shots;
-            machine.pour_with_melody(num_shots);
+        if machine.glass_confirmed(state) {
+            match reason {
+                Reason::SlowReaction => machine.pour_with_melody(num_shots + extra_shots),
+                Reason::EarlyStart(player) | Reason::SlowIndividualReaction(player) => {
+                    machine.pour_with_melody_for_player(player.button_num, num_shots + extra_shots)
+                }
+            }
             machine.wait_for_all_actions();
-            self.state = GameState::WaitForStart;
+            self.state = self.next_loser(machine);
         }
     }
-
-    fn num_players_left(&self) -> usize {
-        let num_players_left = self.num_players / 2;
-        num_players_left
-    }
-
-    fn num_players_right(&self) -> usize {
-        let num_players_left = self.num_players_left();
-        let num_players_right = self.num_players - num_players_left;
-        num_players_right
-    }
 }
 
-fn wait_for_button_presses(
-    machine: &mut Machine,
-    state: &State,
-    left: &mut TeamState,
-    right: &mut TeamState,
-) -> Option<GameState> {
-    let left_won = update_and_get_victory_state(state, PLAYERS_LEFT_SIDE, left);
-    let right_won = update_and_get_victory_state(state, PLAYERS_RIGHT_SIDE, right);
-    if left_won {
-        machine.play_melody(REACTION_TESTER_TEAM_WON_MELODY);
-        Some(GameState::WaitForGlass {
-            team: Team::Right,
-            reason: Reason::SlowReaction,
-        })
-    } else if right_won {
-        machine.play_melody(REACTION_TESTER_TEAM_WON_MELODY);
-        Some(GameState::WaitForGlass {
-            team: Team::Left,
-            reason: Reason::SlowReaction,
-        })
-    } else {
-        None
+impl Program for ReactionTester {
+    /// Only the results are kept, an interrupted round starts over.
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        let mut writer = SnapshotWriter::default();
+        self.leaderboard.snapshot(&mut writer);
+        writer.write_usize(self.early_starts.len());
+        for early_starts in self.early_starts.iter() {
+            writer.write_usize(*early_starts);
+        }
+        writer.write_bool(self.tournament.is_some());
+        if let Some(tournament) = &self.tournament {
+            tournament.snapshot(&mut writer);
+        }
+        Some(writer.finish())
     }
-}
 
-fn update_and_get_victory_state(
-    state: &State,
-    players: &[Player],
-    team_state: &mut TeamState,
-) -> bool {
-    for (i, player) in players.iter().enumerate() {
-        if state.pressed(Switch::number(player.button_num)) {
-            // Make sure we dont panic if somebody presses an out of bounds button
-            if let Some(player_state) = team_state.players_pressed.get_mut(i) {
-                *player_state = true;
-            }
-        }
+    fn restore(&mut self, _machine: &mut Machine, snapshot: &[u8]) {
+        self.restore_snapshot(&mut SnapshotReader::new(snapshot));
     }
-    team_state.won()
-}
 
-impl Program for ReactionTester {
     fn update(&mut self, machine: &mut Machine, state: &State) {
         match self.state {
+            GameState::WaitForStart
+                if self.tournament.is_some()
+                    && state.pressed(Switch::Left)
+                    && state.just_pressed(Switch::Number0) =>
+            {
+                self.reset_tournament();
+                machine.play_melody(CONFIRM_SELECTION);
+                machine.wait_for_all_actions();
+            }
             GameState::WaitForStart => {
                 if self
-                    .iter_active_players()
-                    .all(|(player, _)| state.pressed(Switch::number(player.button_num)))
+                    .players
+                    .iter()
+                    .all(|player| state.pressed(Switch::number(player.button_num)))
                 {
-                    self.state =
-                        get_wait_for_start_state_with_random_timing(machine, &mut self.rng);
+                    self.state = get_wait_for_start_state_with_random_timing(machine);
                     machine.play_melody(REACTION_TESTER_GAME_BEGINS_MELODY);
                     machine.wait_for_all_actions();
                 }
             }
             GameState::WaitForTiming(time) => {
                 if let Some(new_state) = self.wait_for_start(machine, state, time) {
+                    // Reaction times are measured from the start of the melody,
+                    // so we cannot wait for it to finish.
+                    if !matches!(new_state, GameState::WaitForReactions(_)) {
+                        machine.wait_for_all_actions();
+                    }
+                    self.state = new_state;
+                }
+            }
+            GameState::WaitForAllButtonPresses(ref mut press_times) => {
+                let mut press_times = core::mem::take(press_times);
+                if let Some(new_state) =
+                    self.wait_for_button_presses(machine, state, &mut press_times)
+                {
                     self.state = new_state;
                     machine.wait_for_all_actions();
+                } else {
+                    self.state = GameState::WaitForAllButtonPresses(press_times);
                 }
             }
-            GameState::WaitForAllButtonPresses(ref mut left, ref mut right) => {
-                if let Some(new_state) = wait_for_button_presses(machine, state, left, right) {
+            GameState::WaitForReactions(start) => {
+                if let Some(new_state) = self.wait_for_reactions(machine, state, start) {
                     self.state = new_state;
                     machine.wait_for_all_actions();
                 }
@@ -274,3 +566,194 @@ impl Program for ReactionTester {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::GameState;
+    use super::Leaderboard;
+    use super::Mode;
+    use super::ReactionTester;
+    use super::Reason;
+    use crate::configuration::TeamLayout;
+    use crate::hardware_interface::Switch;
+    use crate::hardware_interface::SwitchState;
+    use crate::machine::Machine;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+    use crate::Duration;
+
+    /// Start a round with the default players on buttons 1 and 3 and
+    /// wait for the start signal.
+    fn start_round(mode: Mode) -> Simulation<ReactionTester> {
+        start_round_with_teams(mode, &[&[1], &[3]])
+    }
+
+    fn start_round_with_teams(mode: Mode, teams: &[&[usize]]) -> Simulation<ReactionTester> {
+        let mut machine = Machine::new();
+        machine.get_config_mut().reaction_custom_team_layout = Some(TeamLayout {
+            teams: teams.iter().map(|team| team.to_vec()).collect(),
+        });
+        let program = ReactionTester::new(&machine, mode);
+        let mut sim = Simulation::with_machine(machine, program);
+        let buttons: Vec<Switch> = teams
+            .iter()
+            .flat_map(|team| team.iter())
+            .map(|button_num| Switch::number(*button_num))
+            .collect();
+        sim.press(&buttons);
+        while matches!(
+            sim.program.state,
+            GameState::WaitForStart | GameState::WaitForTiming(_)
+        ) {
+            sim.run_for(STEP_MS);
+        }
+        sim
+    }
+
+    /// Press both buttons within the same update, with the given edge
+    /// times after the current time, and hold them until the round is
+    /// decided.
+    fn press_both(sim: &mut Simulation<ReactionTester>, delay_1_us: u64, delay_3_us: u64) {
+        let time_us = sim.machine.time_us();
+        for (switch, delay_us) in [(Switch::Number1, delay_1_us), (Switch::Number3, delay_3_us)] {
+            sim.interface.pressed[switch] = true;
+            sim.interface.edges_us[switch].record(SwitchState::Pressed, time_us + delay_us);
+        }
+        while !matches!(sim.program.state, GameState::WaitForGlass { .. }) {
+            sim.run_for(STEP_MS);
+        }
+    }
+
+    /// Press every button the given time after the start signal and
+    /// hold them until the round is decided.
+    fn react(sim: &mut Simulation<ReactionTester>, reactions: &[(usize, Duration)]) {
+        let start_ms = sim.machine.time_ms();
+        while !matches!(sim.program.state, GameState::WaitForGlass { .. }) {
+            for (button_num, reaction_ms) in reactions {
+                if sim.machine.time_ms() - start_ms >= *reaction_ms {
+                    sim.interface.pressed[Switch::number(*button_num)] = true;
+                }
+            }
+            sim.run_for(STEP_MS);
+        }
+    }
+
+    /// The buttons of the players who have to drink, in order.
+    fn individual_losers(sim: &Simulation<ReactionTester>) -> Vec<usize> {
+        let GameState::WaitForGlass {
+            reason: Reason::SlowIndividualReaction(first),
+            ..
+        } = sim.program.state
+        else {
+            panic!()
+        };
+        let others = sim.program.losers.iter().rev();
+        core::iter::once(first.button_num)
+            .chain(others.map(|player| player.button_num))
+            .collect()
+    }
+
+    #[test]
+    fn the_leaderboard_is_sorted_by_the_best_time() {
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.record(1, 300_000);
+        leaderboard.record(3, 200_000);
+        leaderboard.record(1, 100_000);
+        leaderboard.record(1, 400_000);
+        let entries: Vec<_> = leaderboard
+            .entries
+            .iter()
+            .map(|entry| (entry.button_num, entry.best, entry.num_rounds))
+            .collect();
+        assert_eq!(entries, [(1, 100_000, 3), (3, 200_000, 1)]);
+    }
+
+    #[test]
+    fn the_leaderboard_is_reported_after_each_round() {
+        let mut sim = start_round(Mode::Individual);
+        react(&mut sim, &[(1, 300), (3, 200)]);
+        let reports = &sim.interface.reports;
+        let leaderboard = reports
+            .iter()
+            .position(|report| report == "Leaderboard:")
+            .unwrap();
+        assert!(reports[leaderboard + 1].starts_with("1. Player 3: 20"));
+        assert!(reports[leaderboard + 2].starts_with("2. Player 1: 30"));
+    }
+
+    #[test]
+    fn only_the_slowest_player_drinks_below_the_threshold() {
+        let mut sim = start_round_with_teams(Mode::Individual, &[&[1, 2], &[3]]);
+        sim.machine.get_config_mut().reaction_time_threshold = 600;
+        react(&mut sim, &[(1, 100), (2, 400), (3, 200)]);
+        assert_eq!(individual_losers(&sim), [2]);
+    }
+
+    #[test]
+    fn everyone_above_the_threshold_drinks() {
+        let mut sim = start_round_with_teams(Mode::Individual, &[&[1, 2], &[3]]);
+        sim.machine.get_config_mut().reaction_time_threshold = 250;
+        react(&mut sim, &[(1, 300), (2, 100), (3, 400)]);
+        assert_eq!(individual_losers(&sim), [1, 3]);
+    }
+
+    fn losing_team(sim: &Simulation<ReactionTester>) -> usize {
+        let GameState::WaitForGlass { team, .. } = sim.program.state else {
+            panic!()
+        };
+        team
+    }
+
+    #[test]
+    fn a_team_is_as_fast_as_its_slowest_member() {
+        let teams: &[&[usize]] = &[&[1, 2], &[3], &[4, 5, 6]];
+        let mut sim = start_round_with_teams(Mode::Teams, teams);
+        react(
+            &mut sim,
+            &[(1, 50), (2, 400), (3, 300), (4, 100), (5, 150), (6, 200)],
+        );
+        assert_eq!(losing_team(&sim), 0);
+    }
+
+    #[test]
+    fn a_larger_team_loses_by_its_slowest_member() {
+        let teams: &[&[usize]] = &[&[1, 2], &[3], &[4, 5, 6]];
+        let mut sim = start_round_with_teams(Mode::Teams, teams);
+        react(
+            &mut sim,
+            &[(1, 250), (2, 300), (3, 350), (4, 50), (5, 100), (6, 450)],
+        );
+        assert_eq!(losing_team(&sim), 2);
+    }
+
+    #[test]
+    fn simultaneous_team_finishes_are_judged_by_edge_time() {
+        let mut sim = start_round(Mode::Teams);
+        press_both(&mut sim, 2000, 1000);
+        assert!(matches!(
+            sim.program.state,
+            GameState::WaitForGlass { team: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn reaction_times_are_measured_at_the_edge() {
+        let mut sim = start_round(Mode::Individual);
+        let GameState::WaitForReactions(start_us) = sim.program.state else {
+            panic!()
+        };
+        let offset_us = sim.machine.time_us() - start_us;
+        press_both(&mut sim, 1234, 1500);
+        let reports = sim.interface.reports.concat();
+        assert!(reports.contains(&alloc::format!(
+            "Player 1: {}",
+            super::format_reaction_time(offset_us + 1234)
+        )));
+        assert!(reports.contains(&alloc::format!(
+            "Player 3: {}",
+            super::format_reaction_time(offset_us + 1500)
+        )));
+    }
+}
@@ -0,0 +1,699 @@
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use rand::prelude::*;
+
+use crate::configuration::EscalationCurve;
+use crate::configuration::RussianRouletteMode;
+use crate::hardware_interface::Led;
+use crate::hardware_interface::State;
+use crate::hardware_interface::Switch;
+use crate::machine::Machine;
+use crate::melody::Length;
+use crate::melody::Note;
+use crate::melody::CHROMATIC_SCALE;
+use crate::melody::CONFIRM_SELECTION;
+use crate::melody::ERROR;
+use crate::melody::RUSSIAN_ROULETTE_PLAYER_NOT_SELECTED;
+use crate::melody::RUSSIAN_ROULETTE_PLAYER_SELECTED;
+use crate::melody::RUSSIAN_ROULETTE_SPIN;
+use crate::melody::RUSSIAN_ROULETTE_SURVIVOR;
+use crate::programs::Program;
+use crate::snapshot::SnapshotReader;
+use crate::snapshot::SnapshotWriter;
+use crate::Duration;
+use crate::Time;
+
+const TENSION_NOTE_BPM: f32 = 200.0;
+const MAX_TENSION_LED_TRANSITION_DURATION: Duration = 500;
+const MIN_TENSION_LED_TRANSITION_DURATION: Duration = 50;
+const TENSION_LED_ON_DURATION: Duration = 100;
+const PLAYER_TONE_BPM: f32 = 200.0;
+/// How much faster the not selected melody plays at the highest risk.
+const NOT_SELECTED_MAX_SPEEDUP: f32 = 3.0;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+enum RussianRouletteGameState {
+    #[default]
+    Registration,
+    PlayerSelection,
+    AwaitingGlass,
+}
+
+#[derive(Debug)]
+struct RoulettePlayer {
+    button_num: usize,
+    num_losses: usize,
+    eliminated: bool,
+}
+
+/// A revolver cylinder. Every pull of the trigger advances it by one
+/// chamber, so a loaded chamber is guaranteed to be hit within one
+/// turn of the cylinder.
+#[derive(Debug)]
+struct Cylinder {
+    chambers: Vec<bool>,
+    position: usize,
+}
+
+impl Cylinder {
+    fn spin(num_chambers: usize, num_loaded: usize, rng: &mut impl Rng) -> Self {
+        let num_loaded = num_loaded.min(num_chambers);
+        let mut chambers = vec![true; num_loaded];
+        chambers.resize(num_chambers, false);
+        chambers.shuffle(rng);
+        Self {
+            chambers,
+            position: 0,
+        }
+    }
+
+    fn pull_trigger(&mut self) -> bool {
+        let loaded = self.chambers[self.position];
+        self.chambers[self.position] = false;
+        self.position = (self.position + 1) % self.chambers.len();
+        loaded
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.chambers.iter().any(|loaded| *loaded)
+    }
+
+    /// The fraction of the loaded chambers among the chambers which
+    /// have not been fired yet.
+    fn tension(&self) -> f32 {
+        let remaining = &self.chambers[self.position..];
+        if remaining.is_empty() {
+            return 1.0;
+        }
+        let num_loaded = remaining.iter().filter(|loaded| **loaded).count();
+        num_loaded as f32 / remaining.len() as f32
+    }
+}
+
+/// The loss probability of the escalating mode. It grows with every
+/// safe pull up to a cap and starts over after a loss.
+#[derive(Debug)]
+struct EscalatingProbability {
+    base: f32,
+    curve: EscalationCurve,
+    step: f32,
+    max: f32,
+    current: f32,
+}
+
+impl EscalatingProbability {
+    fn new(base: f32, curve: EscalationCurve, step: f32, max: f32) -> Self {
+        Self {
+            base,
+            curve,
+            step,
+            max,
+            current: base,
+        }
+    }
+
+    fn pull_trigger(&mut self, rng: &mut impl Rng) -> bool {
+        let lost = rng.gen_bool(self.current as f64);
+        if lost {
+            self.current = self.base;
+        } else {
+            let escalated = match self.curve {
+                EscalationCurve::Linear => self.current + self.step,
+                EscalationCurve::Geometric => self.current * (1.0 + self.step),
+            };
+            // Never decrease the probability, even if the cap is misconfigured.
+            self.current = escalated.min(self.max.max(self.base)).min(1.0);
+        }
+        lost
+    }
+}
+
+#[derive(Debug)]
+pub struct RussianRoulette {
+    mode: RussianRouletteMode,
+    min_num_shots: usize,
+    max_num_shots: usize,
+    probability: f32,
+    num_chambers: usize,
+    num_loaded: usize,
+    cylinder: Cylinder,
+    escalation: EscalatingProbability,
+    state: RussianRouletteGameState,
+    /// If no players registered, anyone can press any button.
+    players: Vec<RoulettePlayer>,
+    current_player: usize,
+    /// Pulls are ignored until the spin melody is over.
+    spinning_until: Time,
+}
+
+impl RussianRoulette {
+    pub fn new(machine: &mut Machine) -> Self {
+        let cylinder = Cylinder::spin(
+            machine.config().russian_roulette_num_chambers,
+            machine.config().russian_roulette_num_loaded,
+            machine.rng(),
+        );
+        let config = machine.config();
+        Self {
+            mode: config.russian_roulette_mode,
+            min_num_shots: config.russian_roulette_min_num_shots,
+            max_num_shots: config.russian_roulette_max_num_shots,
+            probability: config.russian_roulette_loss_probability,
+            num_chambers: config.russian_roulette_num_chambers,
+            num_loaded: config.russian_roulette_num_loaded,
+            cylinder,
+            escalation: EscalatingProbability::new(
+                config.russian_roulette_loss_probability,
+                config.russian_roulette_escalation_curve,
+                config.russian_roulette_escalation_step,
+                config.russian_roulette_max_loss_probability,
+            ),
+            state: RussianRouletteGameState::default(),
+            players: vec![],
+            current_player: 0,
+            spinning_until: 0,
+        }
+    }
+
+    fn play_player_tone(&self, machine: &mut Machine, player: usize) {
+        let tone = Note::new(
+            CHROMATIC_SCALE[player % CHROMATIC_SCALE.len()].freq.clone(),
+            Length::Half,
+            PLAYER_TONE_BPM,
+        );
+        machine.play_melody_after_queued_actions(&[tone]);
+    }
+
+    fn register_players(&mut self, machine: &mut Machine, state: &State) {
+        for switch in state.iter_just_pressed() {
+            if let Some(button_num) = switch.get_num() {
+                if !self
+                    .players
+                    .iter()
+                    .any(|player| player.button_num == button_num)
+                {
+                    self.players.push(RoulettePlayer {
+                        button_num,
+                        num_losses: 0,
+                        eliminated: false,
+                    });
+                    self.play_player_tone(machine, self.players.len() - 1);
+                }
+            }
+        }
+        if state.just_pressed(Switch::Right) {
+            if self.players.len() == 1 {
+                machine.play_melody(ERROR);
+                self.players.clear();
+            } else {
+                machine.play_melody(CONFIRM_SELECTION);
+                self.state = RussianRouletteGameState::PlayerSelection;
+                self.announce_turn(machine);
+            }
+            machine.wait_for_all_actions();
+        }
+    }
+
+    fn announce_turn(&self, machine: &mut Machine) {
+        if !self.players.is_empty() {
+            self.play_player_tone(machine, self.current_player);
+        }
+    }
+
+    fn next_turn(&mut self) {
+        for offset in 1..=self.players.len() {
+            let next = (self.current_player + offset) % self.players.len();
+            if !self.players[next].eliminated {
+                self.current_player = next;
+                return;
+            }
+        }
+    }
+
+    /// Whether the press counts as a pull of the trigger: without
+    /// registered players any button counts, otherwise only the
+    /// button of the player whose turn it is.
+    fn trigger_pulled(&self, state: &State) -> bool {
+        match self.players.get(self.current_player) {
+            Some(player) => state.just_pressed(Switch::number(player.button_num)),
+            None => state.anything_just_pressed(),
+        }
+    }
+
+    /// Eliminate the current player. Once only one player is left,
+    /// they survived and everyone rejoins for the next game.
+    fn eliminate_current_player(&mut self, machine: &mut Machine) {
+        let Some(player) = self.players.get_mut(self.current_player) else {
+            return;
+        };
+        player.num_losses += 1;
+        player.eliminated = true;
+        machine.report(format!(
+            "Player {} lost ({} losses this session)",
+            player.button_num, player.num_losses
+        ));
+        let mut remaining = self.players.iter().filter(|player| !player.eliminated);
+        if let (Some(survivor), None) = (remaining.next(), remaining.next()) {
+            machine.report(format!("Player {} survived!", survivor.button_num));
+            machine.play_melody_after_queued_actions(RUSSIAN_ROULETTE_SURVIVOR);
+            for player in self.players.iter_mut() {
+                player.eliminated = false;
+            }
+        }
+        self.next_turn();
+    }
+
+    fn randomly_select_player(&mut self, machine: &mut Machine) -> bool {
+        match self.mode {
+            RussianRouletteMode::Classic => machine.rng().gen_bool(self.probability as f64),
+            RussianRouletteMode::Revolver => self.cylinder.pull_trigger(),
+            RussianRouletteMode::Escalating => self.escalation.pull_trigger(machine.rng()),
+        }
+    }
+
+    fn get_random_num_shots(&mut self, machine: &mut Machine) -> usize {
+        // in case of misconfiguration
+        let max_num_shots = if self.min_num_shots >= self.max_num_shots {
+            self.min_num_shots
+        } else {
+            self.max_num_shots
+        };
+        machine.rng().gen_range(self.min_num_shots..=max_num_shots)
+    }
+
+    fn reload(&mut self, machine: &mut Machine) {
+        self.cylinder = Cylinder::spin(self.num_chambers, self.num_loaded, machine.rng());
+    }
+
+    /// Does not wait for the melody, so that the right key pressed
+    /// during the spin still completes the exit gesture.
+    fn spin(&mut self, machine: &mut Machine) {
+        self.reload(machine);
+        machine.play_melody(RUSSIAN_ROULETTE_SPIN);
+        let duration: Duration = RUSSIAN_ROULETTE_SPIN.iter().map(Note::total_length).sum();
+        self.spinning_until = machine.time_ms() + duration;
+    }
+
+    /// The higher the risk of the next pull, the faster the beep.
+    fn show_risk(&self, machine: &mut Machine) {
+        let speedup = 1.0 + (NOT_SELECTED_MAX_SPEEDUP - 1.0) * self.escalation.current;
+        let melody: Vec<Note> = RUSSIAN_ROULETTE_PLAYER_NOT_SELECTED
+            .iter()
+            .map(|note| Note {
+                freq: note.freq.clone(),
+                note_length: (note.note_length as f32 / speedup) as Duration,
+                delay_after: (note.delay_after as f32 / speedup) as Duration,
+            })
+            .collect();
+        machine.play_melody(&melody);
+    }
+
+    /// The closer the next pull is to a loaded chamber, the higher
+    /// the tone and the faster the LEDs flash.
+    fn show_tension(&self, machine: &mut Machine) {
+        let tension = self.cylinder.tension();
+        let note = (tension * (CHROMATIC_SCALE.len() - 1) as f32) as usize;
+        machine.play_note(&Note::new(
+            CHROMATIC_SCALE[note].freq.clone(),
+            Length::Quarter,
+            TENSION_NOTE_BPM,
+        ));
+        let transition_duration = MAX_TENSION_LED_TRANSITION_DURATION
+            - ((MAX_TENSION_LED_TRANSITION_DURATION - MIN_TENSION_LED_TRANSITION_DURATION) as f32
+                * tension) as Duration;
+        machine.flash_led(Led::Left, transition_duration, TENSION_LED_ON_DURATION);
+        machine.flash_led(Led::Right, transition_duration, TENSION_LED_ON_DURATION);
+    }
+}
+
+impl RussianRoulette {
+    fn restore_snapshot(&mut self, reader: &mut SnapshotReader) -> Option<()> {
+        let state = match reader.read_u32()? {
+            0 => RussianRouletteGameState::Registration,
+            1 => RussianRouletteGameState::PlayerSelection,
+            _ => RussianRouletteGameState::AwaitingGlass,
+        };
+        let players: Vec<RoulettePlayer> = (0..reader.read_usize()?)
+            .map(|_| {
+                Some(RoulettePlayer {
+                    button_num: reader.read_usize()?,
+                    num_losses: reader.read_usize()?,
+                    eliminated: reader.read_bool()?,
+                })
+            })
+            .collect::<Option<_>>()?;
+        let current_player = reader.read_usize()?;
+        let chambers: Vec<bool> = (0..reader.read_usize()?)
+            .map(|_| reader.read_bool())
+            .collect::<Option<_>>()?;
+        let position = reader.read_usize()?;
+        let escalation = reader.read_f32()?;
+        if current_player >= players.len().max(1) || position >= chambers.len() {
+            return None;
+        }
+        self.state = state;
+        self.players = players;
+        self.current_player = current_player;
+        self.cylinder = Cylinder { chambers, position };
+        self.escalation.current = escalation;
+        Some(())
+    }
+}
+
+impl Program for RussianRoulette {
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        let mut writer = SnapshotWriter::default();
+        writer.write_u32(match self.state {
+            RussianRouletteGameState::Registration => 0,
+            RussianRouletteGameState::PlayerSelection => 1,
+            RussianRouletteGameState::AwaitingGlass => 2,
+        });
+        writer.write_usize(self.players.len());
+        for player in self.players.iter() {
+            writer.write_usize(player.button_num);
+            writer.write_usize(player.num_losses);
+            writer.write_bool(player.eliminated);
+        }
+        writer.write_usize(self.current_player);
+        writer.write_usize(self.cylinder.chambers.len());
+        for loaded in self.cylinder.chambers.iter() {
+            writer.write_bool(*loaded);
+        }
+        writer.write_usize(self.cylinder.position);
+        writer.write_f32(self.escalation.current);
+        Some(writer.finish())
+    }
+
+    fn restore(&mut self, _machine: &mut Machine, snapshot: &[u8]) {
+        self.restore_snapshot(&mut SnapshotReader::new(snapshot));
+    }
+
+    fn update(&mut self, machine: &mut Machine, state: &State) {
+        match self.state {
+            RussianRouletteGameState::Registration => self.register_players(machine, state),
+            RussianRouletteGameState::PlayerSelection => self.test_player_selection(machine, state),
+            RussianRouletteGameState::AwaitingGlass => self.wait_for_glass(machine, state),
+        }
+    }
+}
+
+impl RussianRoulette {
+    fn test_player_selection(&mut self, machine: &mut Machine, state: &State) {
+        if self.mode == RussianRouletteMode::Revolver && state.just_pressed(Switch::Left) {
+            self.spin(machine);
+            return;
+        }
+        if self.trigger_pulled(state) && machine.time_ms() >= self.spinning_until {
+            let selected = self.randomly_select_player(machine);
+            if selected {
+                machine.play_melody(RUSSIAN_ROULETTE_PLAYER_SELECTED);
+                machine.wait_for_all_actions();
+                self.state = RussianRouletteGameState::AwaitingGlass;
+            } else {
+                match self.mode {
+                    RussianRouletteMode::Classic => {
+                        machine.play_melody(RUSSIAN_ROULETTE_PLAYER_NOT_SELECTED)
+                    }
+                    RussianRouletteMode::Revolver => self.show_tension(machine),
+                    RussianRouletteMode::Escalating => self.show_risk(machine),
+                }
+                machine.wait_for_all_actions();
+                self.next_turn();
+                self.announce_turn(machine);
+            }
+        }
+    }
+
+    fn wait_for_glass(&mut self, machine: &mut Machine, state: &State) {
+        if machine.no_ongoing_led_transition() {
+            machine.flash_led(Led::Left, 1000, 1000);
+            machine.flash_led(Led::Right, 1000, 1000);
+        }
+        if machine.glass_confirmed(state) {
+            let num_shots = self.get_random_num_shots(machine);
+            match self.players.get(self.current_player) {
+                Some(player) => machine.pour_with_melody_for_player(player.button_num, num_shots),
+                None => machine.pour_with_melody(num_shots),
+            }
+            machine.wait_for_all_actions();
+            self.state = RussianRouletteGameState::PlayerSelection;
+            self.eliminate_current_player(machine);
+            self.announce_turn(machine);
+            if self.mode == RussianRouletteMode::Revolver && self.cylinder.is_empty() {
+                self.reload(machine);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::Cylinder;
+    use super::EscalatingProbability;
+    use super::RoulettePlayer;
+    use super::RussianRoulette;
+    use super::RussianRouletteGameState;
+    use crate::configuration::EscalationCurve;
+    use crate::configuration::RussianRouletteMode;
+    use crate::hardware_interface::Switch;
+    use crate::machine::Machine;
+    use crate::programs::Program;
+    use crate::random::Random;
+    use crate::test_utils::Simulation;
+
+    const NUM_GAMES: usize = 100_000;
+    const MAX_NUM_PULLS: usize = 16;
+    const TOLERANCE: f32 = 0.01;
+
+    /// Play many games with the same (and therefore repeatedly reset)
+    /// escalating probability and compare the fraction of games lost
+    /// at each pull with the expected distribution.
+    fn check_distribution(mut escalation: EscalatingProbability, probabilities: &[f32]) {
+        for seed in 0..3 {
+            let mut rng = Random::from_seed(seed);
+            let mut histogram = [0usize; MAX_NUM_PULLS];
+            for _ in 0..NUM_GAMES {
+                let mut num_pulls = 0;
+                while !escalation.pull_trigger(&mut rng) {
+                    num_pulls += 1;
+                }
+                histogram[num_pulls.min(MAX_NUM_PULLS - 1)] += 1;
+            }
+            let mut survival = 1.0;
+            for (pull, probability) in probabilities.iter().enumerate() {
+                let expected = survival * probability;
+                let measured = histogram[pull] as f32 / NUM_GAMES as f32;
+                assert!(
+                    (expected - measured).abs() < TOLERANCE,
+                    "pull {}: expected {}, measured {}",
+                    pull,
+                    expected,
+                    measured
+                );
+                survival *= 1.0 - probability;
+            }
+        }
+    }
+
+    #[test]
+    fn linear_escalation() {
+        check_distribution(
+            EscalatingProbability::new(0.1, EscalationCurve::Linear, 0.1, 0.5),
+            &[0.1, 0.2, 0.3, 0.4, 0.5, 0.5, 0.5, 0.5],
+        );
+    }
+
+    #[test]
+    fn geometric_escalation() {
+        check_distribution(
+            EscalatingProbability::new(0.1, EscalationCurve::Geometric, 1.0, 0.6),
+            &[0.1, 0.2, 0.4, 0.6, 0.6, 0.6],
+        );
+    }
+
+    #[test]
+    fn probability_resets_after_loss() {
+        let mut escalation = EscalatingProbability::new(0.2, EscalationCurve::Linear, 0.4, 1.0);
+        let mut rng = Random::from_seed(0);
+        while !escalation.pull_trigger(&mut rng) {}
+        assert_eq!(escalation.current, 0.2);
+        escalation.current = 1.0;
+        assert!(escalation.pull_trigger(&mut rng));
+        assert_eq!(escalation.current, 0.2);
+    }
+
+    #[test]
+    fn every_loaded_chamber_is_hit_within_one_turn() {
+        for seed in 0..100 {
+            let mut rng = Random::from_seed(seed);
+            let mut cylinder = Cylinder::spin(6, 2, &mut rng);
+            let num_losses = (0..6).filter(|_| cylinder.pull_trigger()).count();
+            assert_eq!(num_losses, 2);
+            assert!(cylinder.is_empty());
+        }
+    }
+
+    /// Pull the trigger until someone loses and return the number of
+    /// pulls.
+    fn pull_until_loss(sim: &mut Simulation<RussianRoulette>) -> usize {
+        let mut num_pulls = 0;
+        while sim.program.state == RussianRouletteGameState::PlayerSelection {
+            sim.press(&[Switch::Number1]);
+            sim.run_for(2000);
+            num_pulls += 1;
+        }
+        num_pulls
+    }
+
+    #[test]
+    fn the_revolver_is_reloaded_after_the_last_loaded_chamber() {
+        let mut machine = Machine::new();
+        let config = machine.get_config_mut();
+        config.russian_roulette_mode = RussianRouletteMode::Revolver;
+        config.russian_roulette_num_chambers = 6;
+        config.russian_roulette_num_loaded = 1;
+        let program = RussianRoulette::new(&mut machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        // Play without registering players.
+        sim.press(&[Switch::Right]);
+        for _ in 0..10 {
+            assert!((1..=6).contains(&pull_until_loss(&mut sim)));
+            // Confirm the glass and wait for the pour.
+            sim.press(&[Switch::Number1]);
+            sim.run_for(30_000);
+            assert_eq!(sim.program.state, RussianRouletteGameState::PlayerSelection);
+            assert!(!sim.program.cylinder.is_empty());
+            assert_eq!(sim.program.cylinder.position, 0);
+        }
+    }
+
+    fn registered(buttons: &[usize]) -> Simulation<RussianRoulette> {
+        let mut machine = Machine::new();
+        let program = RussianRoulette::new(&mut machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        for button_num in buttons {
+            sim.press(&[Switch::number(*button_num)]);
+        }
+        sim.press(&[Switch::Right]);
+        // Let the tones of the players finish.
+        sim.run_for(10_000);
+        sim
+    }
+
+    fn current_button(sim: &Simulation<RussianRoulette>) -> usize {
+        sim.program.players[sim.program.current_player].button_num
+    }
+
+    /// Pull the trigger, and confirm the glass if the player lost.
+    fn pull(sim: &mut Simulation<RussianRoulette>, button_num: usize) {
+        sim.press(&[Switch::number(button_num)]);
+        sim.run_for(3000);
+        while sim.program.state == RussianRouletteGameState::AwaitingGlass {
+            sim.press(&[Switch::number(button_num)]);
+            sim.run_for(30_000);
+        }
+    }
+
+    fn pull_current(sim: &mut Simulation<RussianRoulette>) {
+        let button_num = current_button(sim);
+        pull(sim, button_num);
+    }
+
+    #[test]
+    fn players_register_with_their_buttons() {
+        let sim = registered(&[5, 2, 5, 7]);
+        assert_eq!(sim.program.state, RussianRouletteGameState::PlayerSelection);
+        let buttons: Vec<usize> = sim
+            .program
+            .players
+            .iter()
+            .map(|player| player.button_num)
+            .collect();
+        assert_eq!(buttons, [5, 2, 7]);
+        assert_eq!(current_button(&sim), 5);
+    }
+
+    #[test]
+    fn a_single_player_cannot_play_alone() {
+        let sim = registered(&[4]);
+        assert_eq!(sim.program.state, RussianRouletteGameState::Registration);
+        assert!(sim.program.players.is_empty());
+    }
+
+    #[test]
+    fn players_take_turns_in_the_order_of_registration() {
+        let mut sim = registered(&[5, 2, 7]);
+        sim.program.probability = 0.0;
+        // Only the button of the current player pulls the trigger.
+        pull(&mut sim, 2);
+        assert_eq!(current_button(&sim), 5);
+        for expected in [2, 7, 5, 2] {
+            pull_current(&mut sim);
+            assert_eq!(current_button(&sim), expected);
+        }
+    }
+
+    #[test]
+    fn the_last_player_standing_survives() {
+        let mut sim = registered(&[5, 2, 7]);
+        sim.program.probability = 1.0;
+        pull(&mut sim, 5);
+        assert!(sim.program.players[0].eliminated);
+        // Eliminated players are skipped.
+        sim.program.probability = 0.0;
+        for expected in [7, 2, 7] {
+            pull_current(&mut sim);
+            assert_eq!(current_button(&sim), expected);
+        }
+        sim.program.probability = 1.0;
+        pull(&mut sim, 7);
+        assert!(sim.interface.reports.contains(&"Player 2 survived!".into()));
+        assert!(sim.program.players.iter().all(|player| !player.eliminated));
+        let losses: Vec<usize> = sim
+            .program
+            .players
+            .iter()
+            .map(|player| player.num_losses)
+            .collect();
+        assert_eq!(losses, [1, 0, 1]);
+    }
+
+    #[test]
+    fn snapshot_restores_the_game() {
+        let mut machine = Machine::new();
+        let mut roulette = RussianRoulette::new(&mut machine);
+        roulette.state = RussianRouletteGameState::AwaitingGlass;
+        roulette.players = vec![
+            RoulettePlayer {
+                button_num: 3,
+                num_losses: 2,
+                eliminated: true,
+            },
+            RoulettePlayer {
+                button_num: 5,
+                num_losses: 0,
+                eliminated: false,
+            },
+        ];
+        roulette.current_player = 1;
+        roulette.cylinder.pull_trigger();
+        roulette.escalation.current = 0.3;
+        let snapshot = roulette.snapshot().unwrap();
+
+        let mut restored = RussianRoulette::new(&mut machine);
+        restored.restore(&mut machine, &snapshot);
+        assert_eq!(format!("{:?}", restored), format!("{:?}", roulette));
+
+        // Corrupted snapshots are ignored.
+        let mut fresh = RussianRoulette::new(&mut machine);
+        let expected = format!("{:?}", fresh);
+        fresh.restore(&mut machine, &snapshot[..snapshot.len() - 1]);
+        assert_eq!(format!("{:?}", fresh), expected);
+    }
+}
@@ -0,0 +1,694 @@
+//! A small language for games which can be uploaded over the serial
+//! console without reflashing Dwight.
+//!
+//! Every line holds one instruction or a label (`name:`), `#` starts a
+//! comment. Operands are whole numbers or variables, which start at
+//! zero. The instructions are:
+//!
+//! - `set x 5`, `add x y`, `sub x 1`, `mul x 2`, `div x 2`, `mod x 3`
+//! - `random x 1 6`: a random number between both bounds.
+//! - `wait_key x` or `wait_key x 3000`: wait for a key press (number keys
+//!   are 0 to 9, left is 10, right is 11), at most the given ms. A
+//!   timeout stores -1.
+//! - `sleep 500`, `time x`: wait, or store the ms since the start.
+//! - `melody jingle`, `tone 440 200`: play a melody or a single tone.
+//! - `flash left 500`: flash the `left`, `right` or `both` LEDs and wait.
+//! - `pour 2`: pour shots. Reaching the limit of shots per run ends the run.
+//! - `print x`: send the value over the serial console.
+//! - `goto label`, `if x < 3 goto label` (also `<=`, `>`, `>=`, `==`, `!=`).
+//! - `end`: finish the run. A key press starts the next run.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use rand::Rng;
+
+use crate::hardware_interface::Frequency;
+use crate::hardware_interface::Led;
+use crate::hardware_interface::State;
+use crate::hardware_interface::Switch;
+use crate::hardware_interface::STORAGE_SLOT_SIZE;
+use crate::machine::Machine;
+use crate::melody::Melody;
+use crate::melody::Note;
+use crate::melody::BARBIE_GIRL;
+use crate::melody::BEETHOVEN_5;
+use crate::melody::BEETHOVEN_9;
+use crate::melody::CONFIRM_SELECTION;
+use crate::melody::ERROR;
+use crate::melody::IN_PARIS;
+use crate::melody::JINGLE;
+use crate::melody::REACTION_TESTER_TOURNAMENT_VICTORY_MELODY;
+use crate::melody::RUSSIAN_ROULETTE_SPIN;
+use crate::melody::SIMON_SAYS_MISTAKE;
+use crate::programs::Program;
+use crate::Duration;
+use crate::Time;
+
+/// Scripts can never stall the main loop, since at most this many
+/// instructions are executed per iteration.
+const INSTRUCTION_BUDGET_PER_TICK: usize = 100;
+const MAX_NUM_VARIABLES: usize = 32;
+
+const KEY_LEFT: i32 = 10;
+const KEY_RIGHT: i32 = 11;
+const NO_KEY: i32 = -1;
+
+const FLASH_TRANSITION_DURATION_MS: Duration = 100;
+
+const UPLOAD_START: &str = "script";
+const UPLOAD_END: &str = "end script";
+
+const MELODIES: &[(&str, &Melody)] = &[
+    ("jingle", JINGLE),
+    ("confirm", CONFIRM_SELECTION),
+    ("error", ERROR),
+    ("victory", REACTION_TESTER_TOURNAMENT_VICTORY_MELODY),
+    ("mistake", SIMON_SAYS_MISTAKE),
+    ("spin", RUSSIAN_ROULETTE_SPIN),
+    ("beethoven_5", BEETHOVEN_5),
+    ("beethoven_9", BEETHOVEN_9),
+    ("in_paris", IN_PARIS),
+    ("barbie_girl", BARBIE_GIRL),
+];
+
+#[derive(Debug, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: &'static str,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Const(i32),
+    Variable(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Arithmetic {
+    Set,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn parse(token: &str) -> Option<Self> {
+        match token {
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessOrEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterOrEqual),
+            "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            _ => None,
+        }
+    }
+
+    fn holds(&self, left: i32, right: i32) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LedSelection {
+    Left,
+    Right,
+    Both,
+}
+
+#[derive(Clone, Copy)]
+enum Instruction {
+    Arithmetic(Arithmetic, usize, Operand),
+    Random(usize, Operand, Operand),
+    WaitKey(usize, Option<Operand>),
+    Sleep(Operand),
+    Time(usize),
+    Melody(&'static Melody),
+    Tone(Operand, Operand),
+    Flash(LedSelection, Operand),
+    Pour(Operand),
+    Print(Operand),
+    Jump(Option<(Operand, Comparison, Operand)>, usize),
+    End,
+}
+
+/// A compiled script.
+pub struct Script {
+    instructions: Vec<Instruction>,
+    num_variables: usize,
+}
+
+impl Script {
+    pub fn compile(source: &str) -> Result<Self, ScriptError> {
+        let lines: Vec<(usize, &str)> = source
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.split('#').next().unwrap().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .collect();
+        // Labels may be used before they are defined, so they are
+        // collected first.
+        let mut labels: Vec<(&str, usize)> = vec![];
+        let mut num_instructions = 0;
+        for (line_num, line) in lines.iter() {
+            if let Some(label) = line.strip_suffix(':') {
+                if !is_identifier(label) {
+                    return Err(error(*line_num, "invalid label"));
+                }
+                if labels.iter().any(|(other, _)| *other == label) {
+                    return Err(error(*line_num, "duplicate label"));
+                }
+                labels.push((label, num_instructions));
+            } else {
+                num_instructions += 1;
+            }
+        }
+        let mut compiler = Compiler {
+            labels,
+            variables: vec![],
+        };
+        let instructions = lines
+            .iter()
+            .filter(|(_, line)| !line.ends_with(':'))
+            .map(|(line_num, line)| {
+                compiler
+                    .instruction(line)
+                    .map_err(|message| error(*line_num, message))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            instructions,
+            num_variables: compiler.variables.len(),
+        })
+    }
+}
+
+fn error(line: usize, message: &'static str) -> ScriptError {
+    ScriptError { line, message }
+}
+
+fn is_identifier(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && token
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+struct Compiler<'a> {
+    labels: Vec<(&'a str, usize)>,
+    variables: Vec<&'a str>,
+}
+
+impl<'a> Compiler<'a> {
+    fn instruction(&mut self, line: &'a str) -> Result<Instruction, &'static str> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let instruction = match tokens.as_slice() {
+            [op, variable, operand] if arithmetic(op).is_some() => Instruction::Arithmetic(
+                arithmetic(op).unwrap(),
+                self.variable(variable)?,
+                self.operand(operand)?,
+            ),
+            ["random", variable, low, high] => Instruction::Random(
+                self.variable(variable)?,
+                self.operand(low)?,
+                self.operand(high)?,
+            ),
+            ["wait_key", variable] => Instruction::WaitKey(self.variable(variable)?, None),
+            ["wait_key", variable, timeout] => {
+                Instruction::WaitKey(self.variable(variable)?, Some(self.operand(timeout)?))
+            }
+            ["sleep", duration] => Instruction::Sleep(self.operand(duration)?),
+            ["time", variable] => Instruction::Time(self.variable(variable)?),
+            ["melody", name] => Instruction::Melody(
+                MELODIES
+                    .iter()
+                    .find(|(other, _)| other == name)
+                    .map(|(_, melody)| *melody)
+                    .ok_or("unknown melody")?,
+            ),
+            ["tone", frequency, duration] => {
+                Instruction::Tone(self.operand(frequency)?, self.operand(duration)?)
+            }
+            ["flash", led, duration] => {
+                let led = match *led {
+                    "left" => LedSelection::Left,
+                    "right" => LedSelection::Right,
+                    "both" => LedSelection::Both,
+                    _ => return Err("unknown LED"),
+                };
+                Instruction::Flash(led, self.operand(duration)?)
+            }
+            ["pour", num_shots] => Instruction::Pour(self.operand(num_shots)?),
+            ["print", operand] => Instruction::Print(self.operand(operand)?),
+            ["goto", label] => Instruction::Jump(None, self.label(label)?),
+            ["if", left, comparison, right, "goto", label] => Instruction::Jump(
+                Some((
+                    self.operand(left)?,
+                    Comparison::parse(comparison).ok_or("unknown comparison")?,
+                    self.operand(right)?,
+                )),
+                self.label(label)?,
+            ),
+            ["end"] => Instruction::End,
+            _ => return Err("unknown instruction"),
+        };
+        Ok(instruction)
+    }
+
+    fn variable(&mut self, token: &'a str) -> Result<usize, &'static str> {
+        if !is_identifier(token) {
+            return Err("invalid variable name");
+        }
+        if let Some(index) = self.variables.iter().position(|other| *other == token) {
+            return Ok(index);
+        }
+        if self.variables.len() == MAX_NUM_VARIABLES {
+            return Err("too many variables");
+        }
+        self.variables.push(token);
+        Ok(self.variables.len() - 1)
+    }
+
+    fn operand(&mut self, token: &'a str) -> Result<Operand, &'static str> {
+        match token.parse() {
+            Ok(num) => Ok(Operand::Const(num)),
+            Err(_) => self.variable(token).map(Operand::Variable),
+        }
+    }
+
+    fn label(&self, token: &str) -> Result<usize, &'static str> {
+        self.labels
+            .iter()
+            .find(|(label, _)| *label == token)
+            .map(|(_, index)| *index)
+            .ok_or("unknown label")
+    }
+}
+
+fn arithmetic(op: &str) -> Option<Arithmetic> {
+    match op {
+        "set" => Some(Arithmetic::Set),
+        "add" => Some(Arithmetic::Add),
+        "sub" => Some(Arithmetic::Sub),
+        "mul" => Some(Arithmetic::Mul),
+        "div" => Some(Arithmetic::Div),
+        "mod" => Some(Arithmetic::Mod),
+        _ => None,
+    }
+}
+
+/// Collects the lines of a script sent over the serial console,
+/// between `script <slot>` and `end script`.
+pub struct ScriptUpload {
+    pub slot: usize,
+    lines: Vec<String>,
+}
+
+impl ScriptUpload {
+    /// The slot is typed starting from one.
+    pub fn start(line: &str, num_slots: usize) -> Option<Self> {
+        let slot: usize = line.strip_prefix(UPLOAD_START)?.trim().parse().ok()?;
+        (1..=num_slots).contains(&slot).then(|| Self {
+            slot: slot - 1,
+            lines: vec![],
+        })
+    }
+
+    /// Returns true once the upload is complete.
+    pub fn add_line(&mut self, line: String) -> bool {
+        if line.trim() == UPLOAD_END {
+            true
+        } else {
+            self.lines.push(line);
+            false
+        }
+    }
+
+    /// The source of the script, if it is valid.
+    pub fn finish(self) -> Result<String, String> {
+        let source = self.lines.join("\n");
+        if source.len() > STORAGE_SLOT_SIZE {
+            return Err("Script too long".to_string());
+        }
+        Script::compile(&source)
+            .map_err(|err| format!("Script error in line {}: {}", err.line, err.message))?;
+        Ok(source)
+    }
+}
+
+enum Execution {
+    Running,
+    WaitForKey {
+        variable: usize,
+        deadline: Option<Time>,
+    },
+    Sleep(Time),
+    Finished,
+}
+
+/// Runs one of the uploaded scripts.
+pub struct ScriptProgram {
+    script: Option<Script>,
+    variables: Vec<i32>,
+    program_counter: usize,
+    start_time: Time,
+    shots_left: usize,
+    execution: Execution,
+}
+
+impl ScriptProgram {
+    pub fn new(machine: &mut Machine, slot: usize) -> Self {
+        let script = match machine.script(slot).map(Script::compile) {
+            Some(Ok(script)) => Some(script),
+            Some(Err(err)) => {
+                machine.report(format!(
+                    "Script error in line {}: {}",
+                    err.line, err.message
+                ));
+                None
+            }
+            None => {
+                machine.report(format!("No script uploaded to slot {}", slot + 1));
+                None
+            }
+        };
+        if script.is_none() {
+            machine.play_melody_after_queued_actions(ERROR);
+        }
+        let mut program = Self {
+            script,
+            variables: vec![],
+            program_counter: 0,
+            start_time: 0,
+            shots_left: 0,
+            execution: Execution::Finished,
+        };
+        if program.script.is_some() {
+            program.start_run(machine);
+        }
+        program
+    }
+
+    fn start_run(&mut self, machine: &mut Machine) {
+        let Some(script) = &self.script else {
+            return;
+        };
+        self.variables = vec![0; script.num_variables];
+        self.program_counter = 0;
+        self.start_time = machine.time_ms();
+        self.shots_left = machine.config().script_max_num_shots;
+        self.execution = Execution::Running;
+    }
+
+    fn value(&self, operand: Operand) -> i32 {
+        match operand {
+            Operand::Const(num) => num,
+            Operand::Variable(index) => self.variables[index],
+        }
+    }
+
+    fn finish(&mut self, machine: &mut Machine, message: &str) {
+        machine.report(format!("{}, press any key to restart", message));
+        self.execution = Execution::Finished;
+    }
+
+    /// Executes a single instruction and returns whether the script may
+    /// continue within the same iteration of the main loop.
+    fn execute(&mut self, machine: &mut Machine) -> bool {
+        let Some(script) = &self.script else {
+            return false;
+        };
+        let Some(instruction) = script.instructions.get(self.program_counter).copied() else {
+            self.finish(machine, "Script finished");
+            return false;
+        };
+        self.program_counter += 1;
+        match instruction {
+            Instruction::Arithmetic(op, variable, operand) => {
+                let left = self.variables[variable];
+                let right = self.value(operand);
+                let result = match op {
+                    Arithmetic::Set => Some(right),
+                    Arithmetic::Add => Some(left.wrapping_add(right)),
+                    Arithmetic::Sub => Some(left.wrapping_sub(right)),
+                    Arithmetic::Mul => Some(left.wrapping_mul(right)),
+                    Arithmetic::Div => left.checked_div(right),
+                    Arithmetic::Mod => left.checked_rem(right),
+                };
+                let Some(result) = result else {
+                    self.finish(machine, "Script error: division by zero");
+                    return false;
+                };
+                self.variables[variable] = result;
+            }
+            Instruction::Random(variable, low, high) => {
+                let (low, high) = (self.value(low), self.value(high));
+                self.variables[variable] = machine.rng().gen_range(low.min(high)..=low.max(high));
+            }
+            Instruction::WaitKey(variable, timeout) => {
+                let deadline =
+                    timeout.map(|timeout| machine.time_ms() + duration(self.value(timeout)));
+                self.execution = Execution::WaitForKey { variable, deadline };
+                return false;
+            }
+            Instruction::Sleep(operand) => {
+                self.execution =
+                    Execution::Sleep(machine.time_ms() + duration(self.value(operand)));
+                return false;
+            }
+            Instruction::Time(variable) => {
+                self.variables[variable] = machine.time_ms().saturating_sub(self.start_time) as i32;
+            }
+            Instruction::Melody(melody) => {
+                machine.play_melody(melody);
+                machine.wait_for_all_actions();
+                return false;
+            }
+            Instruction::Tone(frequency, length) => {
+                machine.play_note(&Note {
+                    freq: Frequency::Some(self.value(frequency).max(0) as f32),
+                    note_length: duration(self.value(length)),
+                    delay_after: 0,
+                });
+                machine.wait_for_all_actions();
+                return false;
+            }
+            Instruction::Flash(led, on_duration) => {
+                let on_duration = duration(self.value(on_duration));
+                if !matches!(led, LedSelection::Right) {
+                    machine.flash_led(Led::Left, FLASH_TRANSITION_DURATION_MS, on_duration);
+                }
+                if !matches!(led, LedSelection::Left) {
+                    machine.flash_led(Led::Right, FLASH_TRANSITION_DURATION_MS, on_duration);
+                }
+                machine.wait_for_all_actions();
+                return false;
+            }
+            Instruction::Pour(num_shots) => {
+                let requested = self.value(num_shots).max(0) as usize;
+                let num_shots = requested.min(self.shots_left);
+                self.shots_left -= num_shots;
+                machine.pour_with_melody(num_shots);
+                machine.wait_for_all_actions();
+                if num_shots < requested {
+                    self.finish(machine, "Shot limit of the script reached");
+                }
+                return false;
+            }
+            Instruction::Print(operand) => {
+                machine.report(format!("{}", self.value(operand)));
+            }
+            Instruction::Jump(condition, target) => {
+                let jump = condition.is_none_or(|(left, comparison, right)| {
+                    comparison.holds(self.value(left), self.value(right))
+                });
+                if jump {
+                    self.program_counter = target;
+                }
+            }
+            Instruction::End => {
+                self.finish(machine, "Script finished");
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn duration(ms: i32) -> Duration {
+    ms.max(0) as Duration
+}
+
+fn pressed_key(state: &State) -> Option<i32> {
+    if state.just_pressed(Switch::Left) {
+        Some(KEY_LEFT)
+    } else if state.just_pressed(Switch::Right) {
+        Some(KEY_RIGHT)
+    } else {
+        state.lowest_pressed_number_key().map(|key| key as i32)
+    }
+}
+
+impl Program for ScriptProgram {
+    fn update(&mut self, machine: &mut Machine, state: &State) {
+        match self.execution {
+            Execution::Running => {}
+            Execution::WaitForKey { variable, deadline } => {
+                if let Some(key) = pressed_key(state) {
+                    self.variables[variable] = key;
+                } else if deadline.is_some_and(|deadline| machine.time_ms() >= deadline) {
+                    self.variables[variable] = NO_KEY;
+                } else {
+                    return;
+                }
+                self.execution = Execution::Running;
+            }
+            Execution::Sleep(until) => {
+                if machine.time_ms() < until {
+                    return;
+                }
+                self.execution = Execution::Running;
+            }
+            Execution::Finished => {
+                if state.anything_just_pressed() {
+                    self.start_run(machine);
+                }
+                return;
+            }
+        }
+        for _ in 0..INSTRUCTION_BUDGET_PER_TICK {
+            if !self.execute(machine) {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::Script;
+    use super::ScriptError;
+    use super::ScriptProgram;
+    use super::ScriptUpload;
+    use crate::hardware_interface::StorageSlot;
+    use crate::hardware_interface::Switch;
+    use crate::machine::Machine;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+    use crate::Duration;
+
+    const SETTLE_DURATION_MS: Duration = 60_000;
+
+    #[test]
+    fn errors_name_the_line() {
+        let source = "start:\n  set x 1\n\n  goto nowhere\n";
+        assert_eq!(
+            Script::compile(source).err(),
+            Some(ScriptError {
+                line: 4,
+                message: "unknown label"
+            })
+        );
+        assert!(Script::compile("melody silence").is_err());
+        assert!(Script::compile("if x < 3 goto loop\nloop:\nadd x 1 # count").is_ok());
+    }
+
+    fn simulate(source: &str) -> Simulation<ScriptProgram> {
+        let mut machine = Machine::new();
+        machine.set_script(0, source.to_string());
+        let program = ScriptProgram::new(&mut machine, 0);
+        Simulation::with_machine(machine, program)
+    }
+
+    #[test]
+    fn endless_loops_do_not_block_the_machine() {
+        let mut sim = simulate("loop:\ngoto loop");
+        sim.run_for(SETTLE_DURATION_MS);
+        assert!(!sim.interface.relay_on[0]);
+    }
+
+    #[test]
+    fn flashing_waits_for_the_leds() {
+        let mut sim = simulate("loop:\nflash left 500\ngoto loop");
+        for _ in 0..1000 {
+            sim.run_for(STEP_MS);
+            assert!(sim.machine.num_queued_actions() <= 2);
+        }
+    }
+
+    #[test]
+    fn shots_are_limited_per_run() {
+        let mut sim = simulate("loop:\npour 3\ngoto loop");
+        let limit = sim.machine.config().script_max_num_shots;
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(sim.interface.num_pours[0], limit);
+        // A new run starts with a fresh limit.
+        sim.press(&[Switch::number(1)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(sim.interface.num_pours[0], 2 * limit);
+    }
+
+    #[test]
+    fn scripts_wait_for_keys() {
+        let mut sim = simulate("wait_key k\npour k");
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(sim.interface.num_pours[0], 0);
+        sim.press(&[Switch::number(2)]);
+        sim.run_for(SETTLE_DURATION_MS);
+        assert_eq!(sim.interface.num_pours[0], 2);
+    }
+
+    #[test]
+    fn uploaded_scripts_are_stored() {
+        let mut sim = simulate("end");
+        for line in ["script 2", "pour 1", "end script"] {
+            sim.interface.serial_lines.push_back(line.to_string());
+        }
+        sim.run_for(STEP_MS * 4);
+        assert_eq!(sim.machine.script(1), Some("pour 1"));
+        assert_eq!(
+            sim.interface.storage[&StorageSlot::Script(1).index()],
+            b"pour 1"
+        );
+    }
+
+    #[test]
+    fn uploads_are_validated() {
+        let mut upload = ScriptUpload::start("script 2", 3).unwrap();
+        assert_eq!(upload.slot, 1);
+        assert!(!upload.add_line("wait_key k".to_string()));
+        assert!(!upload.add_line("pour k".to_string()));
+        assert!(upload.add_line("end script".to_string()));
+        assert_eq!(upload.finish().unwrap(), "wait_key k\npour k");
+
+        let mut upload = ScriptUpload::start("script 1", 3).unwrap();
+        upload.add_line("jump".to_string());
+        assert!(upload.finish().is_err());
+        assert!(ScriptUpload::start("script 4", 3).is_none());
+    }
+}
@@ -0,0 +1,261 @@
+use alloc::vec::Vec;
+
+use rand::Rng;
+
+use crate::hardware_interface::Led;
+use crate::hardware_interface::State;
+use crate::machine::Machine;
+use crate::melody::CHROMATIC_SCALE;
+use crate::melody::SIMON_SAYS_MISTAKE;
+use crate::melody::SIMON_SAYS_ROUND_COMPLETE;
+use crate::programs::Program;
+use crate::Duration;
+use crate::Time;
+
+const MIN_KEY: usize = 1;
+const MAX_KEY: usize = 9;
+
+const KEY_LED_TRANSITION_DURATION: Duration = 50;
+const KEY_LED_ON_DURATION: Duration = 150;
+
+const GLASS_LED_TRANSITION_DURATION: Duration = 1000;
+const GLASS_LED_ON_DURATION: Duration = 1000;
+
+enum GameState {
+    WaitForStart,
+    ShowSequence(usize),
+    WaitForInput { index: usize, deadline: Time },
+    WaitForGlass { num_shots: usize },
+}
+
+pub struct SimonSays {
+    start_length: usize,
+    length_increment: usize,
+    input_time_limit: Duration,
+    max_num_shots: usize,
+    sequence: Vec<usize>,
+    rounds_completed: usize,
+    state: GameState,
+}
+
+impl SimonSays {
+    pub fn new(machine: &Machine) -> Self {
+        Self {
+            start_length: machine.config().simon_says_start_length,
+            length_increment: machine.config().simon_says_length_increment,
+            input_time_limit: machine.config().simon_says_input_time_limit,
+            max_num_shots: machine.config().simon_says_max_num_shots,
+            sequence: Vec::new(),
+            rounds_completed: 0,
+            state: GameState::WaitForStart,
+        }
+    }
+
+    fn extend_sequence(&mut self, machine: &mut Machine, num: usize) {
+        for _ in 0..num {
+            self.sequence
+                .push(machine.rng().gen_range(MIN_KEY..=MAX_KEY));
+        }
+    }
+
+    fn num_shots(&self) -> usize {
+        // The further the players got, the milder the punishment.
+        self.max_num_shots
+            .saturating_sub(self.rounds_completed)
+            .max(1)
+    }
+
+    fn wait_for_start(&mut self, machine: &mut Machine, state: &State) {
+        if state.anything_just_pressed() {
+            self.sequence.clear();
+            self.rounds_completed = 0;
+            self.extend_sequence(machine, self.start_length);
+            self.state = GameState::ShowSequence(0);
+        }
+    }
+
+    fn show_sequence(&mut self, machine: &mut Machine, index: usize) {
+        if let Some(key) = self.sequence.get(index) {
+            show_key(machine, *key);
+            machine.wait_for_all_actions();
+            self.state = GameState::ShowSequence(index + 1);
+        } else {
+            self.state = GameState::WaitForInput {
+                index: 0,
+                deadline: machine.time_ms() + self.input_time_limit,
+            };
+        }
+    }
+
+    fn wait_for_input(
+        &mut self,
+        machine: &mut Machine,
+        state: &State,
+        index: usize,
+        deadline: Time,
+    ) -> Option<GameState> {
+        let pressed_key = state
+            .iter_just_pressed()
+            .filter_map(|switch| switch.get_num())
+            .find(|num| (MIN_KEY..=MAX_KEY).contains(num));
+        match pressed_key {
+            Some(key) if key == self.sequence[index] => {
+                show_key(machine, key);
+                if index + 1 == self.sequence.len() {
+                    self.rounds_completed += 1;
+                    self.extend_sequence(machine, self.length_increment);
+                    // After the tone of the last key, not on top of it.
+                    machine.play_melody_after_queued_actions(SIMON_SAYS_ROUND_COMPLETE);
+                    machine.wait_for_all_actions();
+                    Some(GameState::ShowSequence(0))
+                } else {
+                    Some(GameState::WaitForInput {
+                        index: index + 1,
+                        deadline: machine.time_ms() + self.input_time_limit,
+                    })
+                }
+            }
+            Some(_) => Some(self.mistake(machine)),
+            None if machine.time_ms() > deadline => Some(self.mistake(machine)),
+            None => None,
+        }
+    }
+
+    fn mistake(&mut self, machine: &mut Machine) -> GameState {
+        machine.play_melody(SIMON_SAYS_MISTAKE);
+        machine.wait_for_all_actions();
+        GameState::WaitForGlass {
+            num_shots: self.num_shots(),
+        }
+    }
+
+    fn wait_for_glass(&mut self, machine: &mut Machine, state: &State, num_shots: usize) {
+        if machine.no_ongoing_led_transition() {
+            machine.flash_led(
+                Led::Left,
+                GLASS_LED_TRANSITION_DURATION,
+                GLASS_LED_ON_DURATION,
+            );
+            machine.flash_led(
+                Led::Right,
+                GLASS_LED_TRANSITION_DURATION,
+                GLASS_LED_ON_DURATION,
+            );
+        }
+        if machine.glass_confirmed(state) {
+            machine.pour_with_melody(num_shots);
+            machine.wait_for_all_actions();
+            self.state = GameState::WaitForStart;
+        }
+    }
+}
+
+/// Play the tone belonging to the key and flash the LED on the
+/// side of the keypad that the key is on (both for the middle column).
+fn show_key(machine: &mut Machine, key: usize) {
+    machine.play_note(&CHROMATIC_SCALE[key - MIN_KEY]);
+    let column = (key - MIN_KEY) % 3;
+    if column != 2 {
+        machine.flash_led(Led::Left, KEY_LED_TRANSITION_DURATION, KEY_LED_ON_DURATION);
+    }
+    if column != 0 {
+        machine.flash_led(Led::Right, KEY_LED_TRANSITION_DURATION, KEY_LED_ON_DURATION);
+    }
+}
+
+impl Program for SimonSays {
+    fn update(&mut self, machine: &mut Machine, state: &State) {
+        match self.state {
+            GameState::WaitForStart => self.wait_for_start(machine, state),
+            GameState::ShowSequence(index) => self.show_sequence(machine, index),
+            GameState::WaitForInput { index, deadline } => {
+                if let Some(new_state) = self.wait_for_input(machine, state, index, deadline) {
+                    self.state = new_state;
+                }
+            }
+            GameState::WaitForGlass { num_shots } => self.wait_for_glass(machine, state, num_shots),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GameState;
+    use super::SimonSays;
+    use crate::hardware_interface::Switch;
+    use crate::machine::Machine;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+
+    const MAX_NUM_SHOTS: usize = 3;
+
+    fn start() -> Simulation<SimonSays> {
+        let mut machine = Machine::new();
+        let config = machine.get_config_mut();
+        config.simon_says_start_length = 2;
+        config.simon_says_length_increment = 1;
+        config.simon_says_max_num_shots = MAX_NUM_SHOTS;
+        let program = SimonSays::new(&machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        sim.press(&[Switch::Right]);
+        wait_for_input(&mut sim);
+        sim
+    }
+
+    fn wait_for_input(sim: &mut Simulation<SimonSays>) {
+        while !matches!(sim.program.state, GameState::WaitForInput { .. }) {
+            sim.run_for(STEP_MS);
+        }
+    }
+
+    fn replay_sequence(sim: &mut Simulation<SimonSays>) {
+        for key in sim.program.sequence.clone() {
+            sim.press(&[Switch::number(key)]);
+        }
+        wait_for_input(sim);
+    }
+
+    /// Press a key which is not the next one of the sequence and pour.
+    fn make_mistake(sim: &mut Simulation<SimonSays>) -> usize {
+        let GameState::WaitForInput { index, .. } = sim.program.state else {
+            panic!()
+        };
+        let wrong_key = sim.program.sequence[index] % 9 + 1;
+        sim.press(&[Switch::number(wrong_key)]);
+        let GameState::WaitForGlass { num_shots } = sim.program.state else {
+            panic!()
+        };
+        sim.run_for(5000);
+        sim.press(&[Switch::Right]);
+        sim.run_for(30_000);
+        assert!(matches!(sim.program.state, GameState::WaitForStart));
+        num_shots
+    }
+
+    #[test]
+    fn replaying_the_sequence_advances_the_round() {
+        let mut sim = start();
+        assert_eq!(sim.program.sequence.len(), 2);
+        replay_sequence(&mut sim);
+        assert_eq!(sim.program.rounds_completed, 1);
+        assert_eq!(sim.program.sequence.len(), 3);
+        replay_sequence(&mut sim);
+        assert_eq!(sim.program.rounds_completed, 2);
+        assert_eq!(sim.interface.num_pours[0], 0);
+    }
+
+    #[test]
+    fn a_wrong_key_pours_the_configured_shots() {
+        let mut sim = start();
+        assert_eq!(make_mistake(&mut sim), MAX_NUM_SHOTS);
+        assert_eq!(sim.interface.num_pours[0], MAX_NUM_SHOTS);
+    }
+
+    #[test]
+    fn progress_earns_fewer_shots() {
+        let mut sim = start();
+        replay_sequence(&mut sim);
+        assert_eq!(make_mistake(&mut sim), MAX_NUM_SHOTS - 1);
+        assert_eq!(sim.interface.num_pours[0], MAX_NUM_SHOTS - 1);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::Time;
+
+/// A pump drawing from a bottle, as seen by a flow meter behind it.
+/// Stands in for the real hardware in the simulator and the tests.
+pub struct SimulatedPump {
+    pub flow_rate_ml_per_s: f32,
+    /// What is left in the bottle.
+    pub remaining_ml: f32,
+    dispensed_ml: f32,
+    running_since: Option<Time>,
+}
+
+impl SimulatedPump {
+    pub fn new(flow_rate_ml_per_s: f32, bottle_ml: f32) -> Self {
+        Self {
+            flow_rate_ml_per_s,
+            remaining_ml: bottle_ml,
+            dispensed_ml: 0.0,
+            running_since: None,
+        }
+    }
+
+    pub fn set_running(&mut self, running: bool, time_ms: Time) {
+        self.update(time_ms);
+        self.running_since = running.then_some(time_ms);
+    }
+
+    /// The total volume which passed the flow meter.
+    pub fn dispensed_ml(&mut self, time_ms: Time) -> f32 {
+        self.update(time_ms);
+        self.dispensed_ml
+    }
+
+    fn update(&mut self, time_ms: Time) {
+        if let Some(since) = self.running_since {
+            let elapsed_ms = time_ms.saturating_sub(since);
+            let volume_ml =
+                (self.flow_rate_ml_per_s * elapsed_ms as f32 / 1000.0).min(self.remaining_ml);
+            self.remaining_ml -= volume_ml;
+            self.dispensed_ml += volume_ml;
+            self.running_since = Some(time_ms);
+        }
+    }
+}
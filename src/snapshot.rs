@@ -0,0 +1,92 @@
+use alloc::vec::Vec;
+
+/// Serializes the state of a program, so that it survives a power loss.
+#[derive(Default)]
+pub struct SnapshotWriter {
+    bytes: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_u32(value as u32);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_u32(value.to_bits());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_usize(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads what a `SnapshotWriter` wrote, in the same order. Every read
+/// returns `None` once the snapshot is exhausted.
+pub struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, num: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < num {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(num);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_usize(&mut self) -> Option<usize> {
+        self.read_u32().map(|value| value as usize)
+    }
+
+    pub fn read_bool(&mut self) -> Option<bool> {
+        Some(self.take(1)?[0] != 0)
+    }
+
+    pub fn read_f32(&mut self) -> Option<f32> {
+        self.read_u32().map(f32::from_bits)
+    }
+
+    pub fn read_bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.read_usize()?;
+        self.take(len)
+    }
+
+    pub fn read_str(&mut self) -> Option<&'a str> {
+        core::str::from_utf8(self.read_bytes()?).ok()
+    }
+}
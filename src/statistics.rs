@@ -0,0 +1,334 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::hardware_interface::Frequency;
+use crate::hardware_interface::State;
+use crate::hardware_interface::Switch;
+use crate::machine::Machine;
+use crate::melody::Length;
+use crate::melody::Note;
+use crate::melody::CONFIRM_SELECTION;
+use crate::programs::Program;
+use crate::programs::PROGRAMS;
+use crate::snapshot::SnapshotReader;
+use crate::snapshot::SnapshotWriter;
+use crate::Duration;
+use crate::Time;
+
+const HOUR_MS: Duration = 60 * 60 * 1000;
+/// Later pours are counted in the last hour, so that the statistics
+/// always fit into their storage slot.
+const MAX_NUM_HOURS: usize = 48;
+
+/// Holding the left key for this long starts a new session.
+const RESET_HOLD_DURATION_MS: Duration = 3000;
+
+const READOUT_BPM: f32 = 240.0;
+const READOUT_TENS_FREQUENCY: Frequency = Frequency::C4;
+const READOUT_ONES_FREQUENCY: Frequency = Frequency::C5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Tally {
+    pub num_shots: f32,
+    pub volume_ml: f32,
+}
+
+impl Tally {
+    fn add(&mut self, other: Tally) {
+        self.num_shots += other.num_shots;
+        self.volume_ml += other.volume_ml;
+    }
+
+    fn write(&self, writer: &mut SnapshotWriter) {
+        writer.write_f32(self.num_shots);
+        writer.write_f32(self.volume_ml);
+    }
+
+    fn read(reader: &mut SnapshotReader) -> Option<Self> {
+        Some(Self {
+            num_shots: reader.read_f32()?,
+            volume_ml: reader.read_f32()?,
+        })
+    }
+}
+
+impl core::fmt::Display for Tally {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:.1} shots, {:.0} ml", self.num_shots, self.volume_ml)
+    }
+}
+
+/// What was poured during the current session, which lasts until it is
+/// reset and continues after a reboot.
+#[derive(Debug, Default)]
+pub struct Statistics {
+    pub total: Tally,
+    pub per_program: Vec<(&'static str, Tally)>,
+    /// By the button of the player, where the game knows who drinks.
+    pub per_player: Vec<(usize, Tally)>,
+    /// By the hour of the session. Only the time Dwight was on counts.
+    pub per_hour: Vec<Tally>,
+    /// The duration of the session before the last boot.
+    elapsed_before_boot_ms: Duration,
+    /// When the part of the session since the last boot started.
+    start_ms: Time,
+}
+
+impl Statistics {
+    /// Start a new session.
+    pub fn reset(&mut self, time_ms: Time) {
+        *self = Self {
+            start_ms: time_ms,
+            ..Self::default()
+        };
+    }
+
+    fn elapsed_ms(&self, time_ms: Time) -> Duration {
+        self.elapsed_before_boot_ms + time_ms.saturating_sub(self.start_ms)
+    }
+
+    pub fn record(
+        &mut self,
+        time_ms: Time,
+        program: Option<&'static str>,
+        player: Option<usize>,
+        tally: Tally,
+    ) {
+        self.total.add(tally);
+        if let Some(program) = program {
+            match self.per_program.iter_mut().find(|(id, _)| *id == program) {
+                Some((_, program_tally)) => program_tally.add(tally),
+                None => self.per_program.push((program, tally)),
+            }
+        }
+        if let Some(player) = player {
+            match self.per_player.iter_mut().find(|(num, _)| *num == player) {
+                Some((_, player_tally)) => player_tally.add(tally),
+                None => self.per_player.push((player, tally)),
+            }
+        }
+        let hour = ((self.elapsed_ms(time_ms) / HOUR_MS) as usize).min(MAX_NUM_HOURS - 1);
+        if self.per_hour.len() <= hour {
+            self.per_hour.resize(hour + 1, Tally::default());
+        }
+        self.per_hour[hour].add(tally);
+    }
+
+    pub fn report_lines(&self, time_ms: Time) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Session of {} min: {}",
+            self.elapsed_ms(time_ms) / 60_000,
+            self.total
+        )];
+        for (program, tally) in self.per_program.iter() {
+            lines.push(format!("Program {}: {}", program, tally));
+        }
+        for (player, tally) in self.per_player.iter() {
+            lines.push(format!("Player {}: {}", player, tally));
+        }
+        for (hour, tally) in self.per_hour.iter().enumerate() {
+            lines.push(format!("Hour {}: {}", hour + 1, tally));
+        }
+        lines
+    }
+
+    pub fn serialize(&self, time_ms: Time) -> Vec<u8> {
+        let mut writer = SnapshotWriter::default();
+        writer.write_u64(self.elapsed_ms(time_ms));
+        self.total.write(&mut writer);
+        writer.write_usize(self.per_program.len());
+        for (program, tally) in self.per_program.iter() {
+            writer.write_str(program);
+            tally.write(&mut writer);
+        }
+        writer.write_usize(self.per_player.len());
+        for (player, tally) in self.per_player.iter() {
+            writer.write_usize(*player);
+            tally.write(&mut writer);
+        }
+        writer.write_usize(self.per_hour.len());
+        for tally in self.per_hour.iter() {
+            tally.write(&mut writer);
+        }
+        writer.finish()
+    }
+
+    /// The session continues at the given time. Programs which no
+    /// longer exist are dropped.
+    pub fn deserialize(data: &[u8], time_ms: Time) -> Option<Self> {
+        let mut reader = SnapshotReader::new(data);
+        let elapsed_before_boot_ms = reader.read_u64()?;
+        let total = Tally::read(&mut reader)?;
+        let mut per_program = vec![];
+        for _ in 0..reader.read_usize()? {
+            let id = reader.read_str()?;
+            let tally = Tally::read(&mut reader)?;
+            if let Some(entry) = PROGRAMS.iter().find(|entry| entry.id == id) {
+                per_program.push((entry.id, tally));
+            }
+        }
+        let per_player = (0..reader.read_usize()?)
+            .map(|_| Some((reader.read_usize()?, Tally::read(&mut reader)?)))
+            .collect::<Option<_>>()?;
+        let per_hour = (0..reader.read_usize()?)
+            .map(|_| Tally::read(&mut reader))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            total,
+            per_program,
+            per_player,
+            per_hour,
+            elapsed_before_boot_ms,
+            start_ms: time_ms,
+        })
+    }
+}
+
+/// Long low beeps for the tens and short high ones for the rest.
+fn readout_melody(num: usize) -> Vec<Note> {
+    let tens = (0..num / 10).map(|_| Note::new(READOUT_TENS_FREQUENCY, Length::Half, READOUT_BPM));
+    let ones =
+        (0..num % 10).map(|_| Note::new(READOUT_ONES_FREQUENCY, Length::Eighth, READOUT_BPM));
+    tens.chain(ones).collect()
+}
+
+/// Reads the statistics of the session out over the serial console and
+/// beeps the number of shots. The right key repeats the read-out,
+/// holding the left key starts a new session.
+pub struct StatisticsProgram {
+    left_pressed_since: Option<Time>,
+}
+
+impl StatisticsProgram {
+    pub fn new(machine: &mut Machine) -> Self {
+        Self::read_out(machine);
+        Self {
+            left_pressed_since: None,
+        }
+    }
+
+    fn read_out(machine: &mut Machine) {
+        let lines = machine.statistics().report_lines(machine.time_ms());
+        for line in lines {
+            machine.report(line);
+        }
+        let num_shots = (machine.statistics().total.num_shots + 0.5) as usize;
+        machine.play_melody_after_queued_actions(&readout_melody(num_shots));
+    }
+}
+
+impl Program for StatisticsProgram {
+    fn update(&mut self, machine: &mut Machine, state: &State) {
+        if state.just_pressed(Switch::Right) {
+            machine.cancel_sounds_and_lights();
+            Self::read_out(machine);
+        }
+        if !state.pressed(Switch::Left) {
+            self.left_pressed_since = None;
+            return;
+        }
+        let since = *self.left_pressed_since.get_or_insert(machine.time_ms());
+        if machine.time_ms() - since >= RESET_HOLD_DURATION_MS {
+            machine.reset_statistics();
+            machine.report("Statistics reset".into());
+            machine.play_melody(CONFIRM_SELECTION);
+            machine.wait_for_all_actions();
+            self.left_pressed_since = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Statistics;
+    use super::StatisticsProgram;
+    use super::Tally;
+    use super::HOUR_MS;
+    use super::RESET_HOLD_DURATION_MS;
+    use crate::hardware_interface::StorageSlot;
+    use crate::hardware_interface::Switch;
+    use crate::machine::Machine;
+    use crate::programs::ProgramSwitching;
+    use crate::test_utils::Simulation;
+    use crate::test_utils::STEP_MS;
+
+    #[test]
+    fn statistics_survive_a_reboot() {
+        let mut statistics = Statistics::default();
+        let shot = Tally {
+            num_shots: 1.0,
+            volume_ml: 20.0,
+        };
+        statistics.record(0, Some("shots"), None, shot);
+        statistics.record(HOUR_MS + 1, Some("russian_roulette"), Some(3), shot);
+        let data = statistics.serialize(2 * HOUR_MS);
+        // After the reboot, the session continues in its third hour.
+        let mut restored = Statistics::deserialize(&data, 0).unwrap();
+        restored.record(1000, Some("shots"), Some(3), shot);
+        assert_eq!(restored.total.num_shots, 3.0);
+        assert_eq!(
+            restored.per_program[0],
+            (
+                "shots",
+                Tally {
+                    num_shots: 2.0,
+                    volume_ml: 40.0
+                }
+            )
+        );
+        assert_eq!(restored.per_player[0].1.volume_ml, 40.0);
+        assert_eq!(restored.per_hour, [shot, shot, shot]);
+    }
+
+    #[test]
+    fn shots_are_counted_per_program() {
+        let mut sim = Simulation::new(ProgramSwitching::default());
+        sim.press(&[Switch::Number2]);
+        // Let the melody of the program finish.
+        sim.run_for(60_000);
+        sim.press(&[Switch::Number3]);
+        sim.run_for(10_000);
+        let total = sim.machine.statistics().total;
+        assert_eq!(total.num_shots, 3.0);
+        assert_eq!(sim.machine.statistics().per_program[0].0, "shots");
+        sim.run_for(60_000);
+        let data = &sim.interface.storage[&StorageSlot::Statistics.index()];
+        let stored = Statistics::deserialize(data, 0).unwrap();
+        assert_eq!(stored.total, total);
+    }
+
+    #[test]
+    fn the_right_key_prints_the_statistics_line_by_line() {
+        let mut machine = Machine::new();
+        let program = StatisticsProgram::new(&mut machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        sim.machine.pour_with_melody(2);
+        sim.run_for(5000);
+        sim.interface.reports.clear();
+        sim.press(&[Switch::Right]);
+        let reports = &sim.interface.reports;
+        assert!(reports[0].starts_with("Session of 0 min: 2.0 shots"));
+        assert!(reports.last().unwrap().starts_with("Hour 1: 2.0 shots"));
+        // The hardware ends every report with a line break of its own.
+        assert!(reports.iter().all(|report| !report.contains('\n')));
+    }
+
+    #[test]
+    fn holding_left_resets_the_session() {
+        let mut machine = Machine::new();
+        let program = StatisticsProgram::new(&mut machine);
+        let mut sim = Simulation::with_machine(machine, program);
+        sim.machine.pour_with_melody(2);
+        sim.run_for(5000);
+        let num_shots = sim.machine.statistics().total.num_shots;
+        assert!((1.9..=2.1).contains(&num_shots));
+        sim.interface.pressed[Switch::Left] = true;
+        sim.run_for(RESET_HOLD_DURATION_MS / 2);
+        assert_eq!(sim.machine.statistics().total.num_shots, num_shots);
+        sim.run_for(RESET_HOLD_DURATION_MS / 2 + STEP_MS);
+        assert_eq!(sim.machine.statistics().total.num_shots, 0.0);
+        assert!(sim.interface.reports.contains(&"Statistics reset".into()));
+    }
+}
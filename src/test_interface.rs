@@ -1,5 +1,11 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::fs;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
 use std::io::BufRead;
 use std::io::{self};
+use std::path::PathBuf;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::RecvTimeoutError;
@@ -13,18 +19,29 @@ use dwight::hardware_interface::HardwareInterface;
 use dwight::hardware_interface::Led;
 use dwight::hardware_interface::LedState;
 use dwight::hardware_interface::RelayState;
+use dwight::hardware_interface::StorageSlot;
 use dwight::hardware_interface::Switch;
 use dwight::hardware_interface::SwitchState;
+use dwight::hardware_interface::NUM_PUMPS;
 use dwight::main_loop;
+use dwight::simulated_pump::SimulatedPump;
 use dwight::Time;
 
 pub const RECV_TIMEOUT_MS: u64 = 5;
 pub const PRESSED_DURATION_MS: u128 = 1000;
+/// The same for all pumps. Override with the environment variables
+/// `DWIGHT_FLOW_RATE_ML_PER_S` and `DWIGHT_BOTTLE_ML`.
+pub const DEFAULT_FLOW_RATE_ML_PER_S: f32 = 25.0;
+pub const DEFAULT_BOTTLE_ML: f32 = 700.0;
 
 struct TestDwight {
     input_reader: InputReader,
     pressed: Vec<(Instant, Switch)>,
+    serial_lines: VecDeque<String>,
     start: Instant,
+    pumps: [SimulatedPump; NUM_PUMPS],
+    bottle_ml: f32,
+    glass_present: bool,
 }
 
 impl TestDwight {
@@ -32,20 +49,38 @@ impl TestDwight {
         Self {
             input_reader: InputReader::new(),
             pressed: vec![],
+            serial_lines: VecDeque::new(),
             start: Instant::now(),
+            pumps: std::array::from_fn(|_| {
+                SimulatedPump::new(
+                    env_or("DWIGHT_FLOW_RATE_ML_PER_S", DEFAULT_FLOW_RATE_ML_PER_S),
+                    env_or("DWIGHT_BOTTLE_ML", DEFAULT_BOTTLE_ML),
+                )
+            }),
+            bottle_ml: env_or("DWIGHT_BOTTLE_ML", DEFAULT_BOTTLE_ML),
+            glass_present: true,
         }
     }
 }
 
 impl TestDwight {
+    /// Lines which are not the name of a switch are treated as if they
+    /// were received over the serial console, except for `refill`,
+    /// which replaces all simulated bottles, and `glass`, which takes the
+    /// glass away or puts it back.
     fn update_switches(&mut self) {
         let now = Instant::now();
-        while let Some(switch) = self
-            .input_reader
-            .next_input()
-            .and_then(|input| input_to_switch(&input))
-        {
-            self.pressed.push((now, switch));
+        while let Some(input) = self.input_reader.next_input() {
+            match input_to_switch(&input) {
+                Some(switch) => self.pressed.push((now, switch)),
+                None if input == "refill" => {
+                    for pump in self.pumps.iter_mut() {
+                        pump.remaining_ml = self.bottle_ml;
+                    }
+                }
+                None if input == "glass" => self.glass_present = !self.glass_present,
+                None => self.serial_lines.push_back(input),
+            }
         }
         self.pressed = self
             .pressed
@@ -73,8 +108,10 @@ impl HardwareInterface for TestDwight {
 
     fn set_led_state(&mut self, _led: Led, _led_state: LedState) {}
 
-    fn set_relay_state(&mut self, relay_state: RelayState) {
-        dbg!(relay_state);
+    fn set_relay_state(&mut self, pump: usize, relay_state: RelayState) {
+        let time_ms = self.get_elapsed_time_ms();
+        self.pumps[pump].set_running(matches!(relay_state, RelayState::On), time_ms);
+        dbg!(pump, relay_state);
     }
 
     fn set_speaker_frequency(&mut self, frequency: &Frequency) {
@@ -82,28 +119,70 @@ impl HardwareInterface for TestDwight {
     }
 
     fn wait_ms(&mut self, delay_ms: dwight::Duration) {
-        thread::sleep(Duration::from_millis(delay_ms as u64));
+        thread::sleep(Duration::from_millis(delay_ms));
     }
 
     fn get_elapsed_time_ms(&mut self) -> Time {
         Instant::now().duration_since(self.start).as_millis() as Time
     }
+
+    fn get_flow_meter_volume_ml(&mut self, pump: usize) -> Option<f32> {
+        let time_ms = self.get_elapsed_time_ms();
+        Some(self.pumps[pump].dispensed_ml(time_ms))
+    }
+
+    fn is_glass_present(&mut self) -> Option<bool> {
+        Some(self.glass_present)
+    }
+
+    fn get_random_bits(&mut self) -> u32 {
+        RandomState::new().build_hasher().finish() as u32
+    }
+
+    fn report(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    fn read_serial_line(&mut self) -> Option<String> {
+        self.serial_lines.pop_front()
+    }
+
+    fn read_storage(&mut self, slot: StorageSlot) -> Vec<u8> {
+        fs::read(storage_path(slot)).unwrap_or_default()
+    }
+
+    fn write_storage(&mut self, slot: StorageSlot, data: &[u8]) {
+        let path = storage_path(slot);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
 }
 
-fn input_to_switch(input: &str) -> Option<Switch> {
-    input
-        .parse::<usize>()
+/// The storage is kept in files, so that it survives restarts just like
+/// the flash memory on the hardware.
+fn storage_path(slot: StorageSlot) -> PathBuf {
+    std::env::temp_dir()
+        .join("dwight")
+        .join(format!("slot_{}", slot.index()))
+}
+
+fn env_or(name: &str, default: f32) -> f32 {
+    std::env::var(name)
         .ok()
-        .map(|num| Switch::number(num))
-        .or_else(|| {
-            if input == "left" {
-                Some(Switch::Left)
-            } else if input == "right" {
-                Some(Switch::Right)
-            } else {
-                None
-            }
-        })
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn input_to_switch(input: &str) -> Option<Switch> {
+    input.parse::<usize>().ok().map(Switch::number).or_else(|| {
+        if input == "left" {
+            Some(Switch::Left)
+        } else if input == "right" {
+            Some(Switch::Right)
+        } else {
+            None
+        }
+    })
 }
 
 struct InputReader {
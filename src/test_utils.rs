@@ -0,0 +1,181 @@
+extern crate std;
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::string::String;
+use std::vec::Vec;
+
+use enum_map::EnumMap;
+
+use crate::hardware_interface::Frequency;
+use crate::hardware_interface::HardwareInterface;
+use crate::hardware_interface::Led;
+use crate::hardware_interface::LedState;
+use crate::hardware_interface::RelayState;
+use crate::hardware_interface::State;
+use crate::hardware_interface::StorageSlot;
+use crate::hardware_interface::Switch;
+use crate::hardware_interface::SwitchEdges;
+use crate::hardware_interface::SwitchState;
+use crate::hardware_interface::NUM_PUMPS;
+use crate::machine::Machine;
+use crate::programs::Program;
+use crate::simulated_pump::SimulatedPump;
+use crate::Duration;
+use crate::Time;
+use crate::TimeUs;
+
+/// The duration of a single iteration of the simulated main loop.
+pub const STEP_MS: Duration = 5;
+
+/// A hardware interface for host tests which is fully controlled by
+/// the test: time only advances when the test says so, switches are
+/// pressed explicitly and the random bits are deterministic.
+#[derive(Default)]
+pub struct MockInterface {
+    pub time_ms: Time,
+    pub pressed: EnumMap<Switch, bool>,
+    /// Edge times handed out once, as if captured by an interrupt.
+    pub edges_us: EnumMap<Switch, SwitchEdges>,
+    pub relay_on: [bool; NUM_PUMPS],
+    /// How often the relay of each pump was switched on.
+    pub num_pours: [usize; NUM_PUMPS],
+    pub speaker_on: bool,
+    pub reports: Vec<String>,
+    pub serial_lines: VecDeque<String>,
+    pub storage: HashMap<usize, Vec<u8>>,
+    /// The pumps which have a flow meter.
+    pub pumps: [Option<SimulatedPump>; NUM_PUMPS],
+    /// What the glass sensor reports, if there is one.
+    pub glass_present: Option<bool>,
+}
+
+impl HardwareInterface for MockInterface {
+    fn get_switch_state(&mut self, switch: Switch) -> SwitchState {
+        if self.pressed[switch] {
+            SwitchState::Pressed
+        } else {
+            SwitchState::Released
+        }
+    }
+
+    fn set_led_state(&mut self, _led: Led, _led_state: LedState) {}
+
+    fn set_relay_state(&mut self, pump: usize, relay_state: RelayState) {
+        let relay_on = matches!(relay_state, RelayState::On);
+        if relay_on && !self.relay_on[pump] {
+            self.num_pours[pump] += 1;
+        }
+        self.relay_on[pump] = relay_on;
+        if let Some(simulated_pump) = &mut self.pumps[pump] {
+            simulated_pump.set_running(relay_on, self.time_ms);
+        }
+    }
+
+    fn set_speaker_frequency(&mut self, frequency: &Frequency) {
+        self.speaker_on = matches!(frequency, Frequency::Some(_));
+    }
+
+    fn wait_ms(&mut self, delay_ms: Duration) {
+        self.time_ms += delay_ms;
+    }
+
+    fn get_elapsed_time_ms(&mut self) -> Time {
+        self.time_ms
+    }
+
+    fn take_switch_edge_us(&mut self, switch: Switch, state: SwitchState) -> Option<TimeUs> {
+        self.edges_us[switch].take(state)
+    }
+
+    fn get_flow_meter_volume_ml(&mut self, pump: usize) -> Option<f32> {
+        let time_ms = self.time_ms;
+        self.pumps[pump]
+            .as_mut()
+            .map(|simulated_pump| simulated_pump.dispensed_ml(time_ms))
+    }
+
+    fn is_glass_present(&mut self) -> Option<bool> {
+        self.glass_present
+    }
+
+    fn get_random_bits(&mut self) -> u32 {
+        0
+    }
+
+    fn report(&mut self, message: &str) {
+        self.reports.push(message.into());
+    }
+
+    fn read_serial_line(&mut self) -> Option<String> {
+        self.serial_lines.pop_front()
+    }
+
+    fn read_storage(&mut self, slot: StorageSlot) -> Vec<u8> {
+        self.storage.get(&slot.index()).cloned().unwrap_or_default()
+    }
+
+    fn write_storage(&mut self, slot: StorageSlot, data: &[u8]) {
+        self.storage.insert(slot.index(), data.into());
+    }
+}
+
+/// Runs a program on a machine with a mock interface.
+pub struct Simulation<P: Program> {
+    pub machine: Machine,
+    pub interface: MockInterface,
+    pub program: P,
+    state: Option<State>,
+}
+
+impl<P: Program> Simulation<P> {
+    pub fn new(program: P) -> Self {
+        Self::with_machine(Machine::new(), program)
+    }
+
+    pub fn with_machine(machine: Machine, program: P) -> Self {
+        Self {
+            machine,
+            interface: MockInterface::default(),
+            program,
+            state: Some(State::new()),
+        }
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.interface.time_ms + duration;
+        while self.interface.time_ms < end {
+            let state = self.state.take().unwrap();
+            self.state = Some(
+                self.machine
+                    .step(&mut self.interface, &mut self.program, state),
+            );
+            self.interface.time_ms += STEP_MS;
+        }
+    }
+
+    /// Like `run_for`, returns how long the relay of the pump was on.
+    pub fn run_counting_relay_for(&mut self, pump: usize, duration: Duration) -> Duration {
+        let mut relay_on_ms = 0;
+        for _ in 0..duration / STEP_MS {
+            self.run_for(STEP_MS);
+            if self.interface.relay_on[pump] {
+                relay_on_ms += STEP_MS;
+            }
+        }
+        relay_on_ms
+    }
+
+    /// Press the switches simultaneously, hold them for a moment and
+    /// release them again.
+    pub fn press(&mut self, switches: &[Switch]) {
+        for switch in switches {
+            self.interface.pressed[*switch] = true;
+        }
+        self.run_for(STEP_MS * 4);
+        for switch in switches {
+            self.interface.pressed[*switch] = false;
+        }
+        self.run_for(STEP_MS * 4);
+    }
+}
@@ -0,0 +1,221 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::configuration::TournamentFormat;
+use crate::machine::Machine;
+use crate::snapshot::SnapshotReader;
+use crate::snapshot::SnapshotWriter;
+
+/// Keeps score across the rounds of a game. Participants are
+/// identified by their index, which can be a team or a single player
+/// depending on the game.
+pub struct Tournament {
+    format: TournamentFormat,
+    length: usize,
+    shot_escalation: usize,
+    names: Vec<String>,
+    num_rounds: usize,
+    scores: Vec<usize>,
+    losses: Vec<usize>,
+}
+
+impl Tournament {
+    pub fn new(
+        format: TournamentFormat,
+        length: usize,
+        shot_escalation: usize,
+        names: Vec<String>,
+    ) -> Self {
+        let num_participants = names.len();
+        Self {
+            format,
+            length,
+            shot_escalation,
+            names,
+            num_rounds: 0,
+            scores: vec![0; num_participants],
+            losses: vec![0; num_participants],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.num_rounds = 0;
+        self.scores.iter_mut().for_each(|score| *score = 0);
+        self.losses.iter_mut().for_each(|losses| *losses = 0);
+    }
+
+    /// Every participant who did not lose the round scores a point.
+    pub fn record_round(&mut self, losers: &[usize]) {
+        self.num_rounds += 1;
+        for (participant, score) in self.scores.iter_mut().enumerate() {
+            if losers.contains(&participant) {
+                self.losses[participant] += 1;
+            } else {
+                *score += 1;
+            }
+        }
+    }
+
+    /// The number of shots added to the regular punishment of a
+    /// participant who has lost more than once.
+    pub fn extra_shots(&self, participant: usize) -> usize {
+        self.losses[participant].saturating_sub(1) * self.shot_escalation
+    }
+
+    fn sorted_scores(&self) -> Vec<usize> {
+        let mut scores = self.scores.clone();
+        scores.sort_unstable_by(|a, b| b.cmp(a));
+        scores
+    }
+
+    pub fn is_over(&self) -> bool {
+        let scores = self.sorted_scores();
+        let leader = scores.first().copied().unwrap_or(0);
+        let second = scores.get(1).copied().unwrap_or(0);
+        match self.format {
+            TournamentFormat::BestOf => {
+                let remaining_rounds = self.length.saturating_sub(self.num_rounds);
+                remaining_rounds == 0 || leader - second > remaining_rounds
+            }
+            TournamentFormat::FirstTo => leader >= self.length,
+        }
+    }
+
+    pub fn winners(&self) -> impl Iterator<Item = usize> + '_ {
+        let best = self.scores.iter().copied().max().unwrap_or(0);
+        self.scores
+            .iter()
+            .enumerate()
+            .filter(move |(_, score)| **score == best)
+            .map(|(participant, _)| participant)
+    }
+
+    pub fn report_scores(&self, machine: &mut Machine) {
+        machine.report(format!("Scores after round {}:", self.num_rounds));
+        for (participant, (score, losses)) in self.scores.iter().zip(self.losses.iter()).enumerate()
+        {
+            machine.report(format!(
+                "{}: {} points, {} losses",
+                self.name(participant),
+                score,
+                losses
+            ));
+        }
+    }
+
+    pub fn name(&self, participant: usize) -> &str {
+        &self.names[participant]
+    }
+
+    pub fn snapshot(&self, writer: &mut SnapshotWriter) {
+        writer.write_usize(self.num_rounds);
+        for (score, losses) in self.scores.iter().zip(self.losses.iter()) {
+            writer.write_usize(*score);
+            writer.write_usize(*losses);
+        }
+    }
+
+    /// Fails if the snapshot is from a tournament with other participants.
+    pub fn restore(&mut self, reader: &mut SnapshotReader) -> Option<()> {
+        let num_rounds = reader.read_usize()?;
+        let mut scores = vec![];
+        let mut losses = vec![];
+        for _ in 0..self.names.len() {
+            scores.push(reader.read_usize()?);
+            losses.push(reader.read_usize()?);
+        }
+        self.num_rounds = num_rounds;
+        self.scores = scores;
+        self.losses = losses;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use super::Tournament;
+    use crate::configuration::TournamentFormat;
+    use crate::snapshot::SnapshotReader;
+    use crate::snapshot::SnapshotWriter;
+
+    fn three_way(format: TournamentFormat, length: usize) -> Tournament {
+        let names = ["left", "right", "middle"].map(String::from).to_vec();
+        Tournament::new(format, length, 2, names)
+    }
+
+    #[test]
+    fn best_of_ends_once_the_lead_cannot_be_caught_up() {
+        let mut tournament = three_way(TournamentFormat::BestOf, 5);
+        tournament.record_round(&[1, 2]);
+        tournament.record_round(&[1, 2]);
+        assert!(!tournament.is_over());
+        // Three points ahead with two rounds to go.
+        tournament.record_round(&[1, 2]);
+        assert!(tournament.is_over());
+        assert_eq!(tournament.winners().collect::<Vec<_>>(), [0]);
+    }
+
+    #[test]
+    fn best_of_ends_after_all_rounds_even_when_tied() {
+        let mut tournament = three_way(TournamentFormat::BestOf, 2);
+        tournament.record_round(&[1, 2]);
+        assert!(!tournament.is_over());
+        tournament.record_round(&[0, 2]);
+        assert!(tournament.is_over());
+        assert_eq!(tournament.winners().collect::<Vec<_>>(), [0, 1]);
+    }
+
+    #[test]
+    fn first_to_ends_once_somebody_has_enough_points() {
+        let mut tournament = three_way(TournamentFormat::FirstTo, 2);
+        for _ in 0..5 {
+            tournament.record_round(&[0, 1, 2]);
+        }
+        assert!(!tournament.is_over());
+        tournament.record_round(&[0, 1]);
+        tournament.record_round(&[0]);
+        assert!(tournament.is_over());
+        assert_eq!(tournament.winners().collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn repeated_losses_cost_extra_shots() {
+        let mut tournament = three_way(TournamentFormat::FirstTo, 10);
+        tournament.record_round(&[0]);
+        assert_eq!(tournament.extra_shots(0), 0);
+        tournament.record_round(&[0, 1]);
+        tournament.record_round(&[0]);
+        assert_eq!(tournament.extra_shots(0), 4);
+        assert_eq!(tournament.extra_shots(1), 0);
+        assert_eq!(tournament.extra_shots(2), 0);
+        tournament.reset();
+        assert_eq!(tournament.extra_shots(0), 0);
+        assert_eq!(tournament.winners().count(), 3);
+        assert!(!tournament.is_over());
+    }
+
+    #[test]
+    fn the_score_survives_a_snapshot() {
+        let mut tournament = three_way(TournamentFormat::BestOf, 3);
+        tournament.record_round(&[1]);
+        tournament.record_round(&[1, 2]);
+        let mut writer = SnapshotWriter::default();
+        tournament.snapshot(&mut writer);
+        let data = writer.finish();
+        let mut restored = three_way(TournamentFormat::BestOf, 3);
+        restored.restore(&mut SnapshotReader::new(&data)).unwrap();
+        assert_eq!(restored.extra_shots(1), 2);
+        assert_eq!(restored.winners().collect::<Vec<_>>(), [0]);
+        restored.record_round(&[2]);
+        assert!(restored.is_over());
+        // A tournament with more participants does not fit the snapshot.
+        let names = ["a", "b", "c", "d"].map(String::from).to_vec();
+        let mut other = Tournament::new(TournamentFormat::BestOf, 3, 2, names);
+        assert!(other.restore(&mut SnapshotReader::new(&data)).is_none());
+    }
+}